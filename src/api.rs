@@ -1,22 +1,47 @@
 use super::*;
 use reqwest::*;
-
-pub const API: &str = "http://wiki.bibanon.org/api.php";
-
+use cookie_store::CookieStore;
+use std::io::{BufReader, Write};
+
+pub const DEFAULT_API: &str = "http://wiki.bibanon.org/api.php";
+pub const DEFAULT_RETRIES: u32 = 5;
+//seconds of replication lag the wiki may have before refusing us, as recommended for bots
+pub const DEFAULT_MAXLAG: u32 = 5;
+//most titles a query takes at once without bot rights
+pub const QUERY_BATCH: usize = 50;
+const MAX_BACKOFF: u64 = 120;
+
+//one client for the whole run so connections to the wiki are kept alive and reused
 pub struct MwClient {
     client: Client,
-    cookies: HashMap<String, String>,
+    api: String,
+    url: Url,
+    bearer: Option<String>,
+    cookies: CookieStore,
+    session: Option<PathBuf>,
+    //what is in the session file, so it is only written when the cookies change
+    saved: Vec<u8>,
+    creds: Option<Creds>,
+    pub retries: u32,
+    pub maxlag: Option<u32>,
     pub token: String
 }
 
-#[derive(Serialize, Debug)]
+//password is only fetched once a login is actually needed
+pub struct Creds {
+    user: String,
+    password: Option<String>,
+    get_password: Box<dyn Fn() -> Res<String>>
+}
+
+#[derive(Serialize, Debug, Clone)]
 pub struct MwArticle {
     pub title: String,
     pub text: String,
     pub summary: String
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 #[serde(tag = "action")]
 #[serde(rename_all = "lowercase")]
 enum Action {
@@ -24,21 +49,26 @@ enum Action {
     #[serde(rename = "query")] UiQuery { meta: String, uiprop: String },
     CheckToken { token: String, #[serde(rename = "type")] type_: String },
     Login {  lgname: String, lgpassword: String, lgtoken: String },
-    #[serde(rename = "edit")] EditArticle {#[serde(flatten)] article: MwArticle, bot: bool, token: String},
-    Upload { filename: String, filepath: PathBuf, token: String }
+    #[serde(rename = "query")] RevQuery { prop: String, titles: String, rvprop: String, rvslots: Option<String>, formatversion: u32 },
+    #[serde(rename = "query")] AllPages { list: String, apprefix: String, aplimit: String, apcontinue: Option<String> },
+    #[serde(rename = "query")] ImageInfo { prop: String, titles: String, iiprop: String, formatversion: u32 },
+    #[serde(rename = "query")] Titles { titles: String, formatversion: u32 },
+    #[serde(rename = "edit")] EditArticle {
+        #[serde(flatten)] article: MwArticle, bot: bool,
+        basetimestamp: Option<String>, baserevid: Option<u64>, token: String
+    },
+    Upload { filename: String, filepath: PathBuf, ignorewarnings: bool, token: String },
+    Delete { title: String, reason: String, token: String },
+    Move { from: String, to: String, reason: String, movetalk: bool, token: String }
 }
 
 #[derive(Serialize, Debug)]
 struct Params {
     #[serde(flatten)]
     action: Action,
-    format: String
-}
-
-#[derive(Serialize, Debug)]
-#[serde(rename_all = "PascalCase")]
-struct Preflight {
-    origin: String
+    format: String,
+    assert: Option<String>,
+    maxlag: Option<u32>
 }
 
 #[derive(Deserialize, Debug)]
@@ -93,50 +123,351 @@ struct CheckToken {
     checktoken: CheckTokenRes
 }
 
-#[derive(Fail, Deserialize, Debug)]
-#[fail(display = "Api Error: {}. {}", code, info)]
-pub struct ApiErr {code: String, info: String}
+#[derive(Deserialize, Debug)]
+struct EditRes {
+    result: String,
+    #[serde(flatten)]
+    other: HashMap<String, serde_json::Value>
+}
+
+#[derive(Deserialize, Debug)]
+struct Edit {
+    edit: EditRes
+}
+
+//what we last published of a page, sent back as the base of the next edit
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Revision {
+    pub revid: u64,
+    pub timestamp: String
+}
 
 #[derive(Deserialize, Debug)]
-pub struct Api<T> {
-    batchcomplete: Option<String>,
+struct Slot {
+    content: String
+}
 
-    #[serde(flatten)]
-    res: T
+#[derive(Deserialize, Debug)]
+pub struct PageRev {
+    pub revid: u64,
+    pub timestamp: String,
+    #[serde(default)]
+    pub user: String,
+    //older wikis put the text right on the revision, newer ones in the main slot
+    content: Option<String>,
+    slots: Option<HashMap<String, Slot>>
+}
+
+impl PageRev {
+    pub fn revision(&self) -> Revision {
+        Revision {revid: self.revid, timestamp: self.timestamp.clone()}
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        self.slots.as_ref().and_then(|x| x.get("main")).map(|x| x.content.as_str())
+            .or_else(|| self.content.as_ref().map(|x| x.as_str()))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct RevPage {
+    title: String,
+    #[serde(default)]
+    revisions: Vec<PageRev>
+}
+
+#[derive(Deserialize, Debug)]
+struct RevPages {
+    pages: Vec<RevPage>
+}
+
+#[derive(Deserialize, Debug)]
+struct PageTitle {
+    title: String
+}
+
+#[derive(Deserialize, Debug)]
+struct AllPages {
+    allpages: Vec<PageTitle>
+}
+
+#[derive(Deserialize, Debug)]
+struct AllPagesContinue {
+    apcontinue: String
+}
+
+#[derive(Deserialize, Debug)]
+struct AllPagesRes {
+    query: AllPages,
+    #[serde(rename = "continue")]
+    cont: Option<AllPagesContinue>
+}
+
+#[derive(Deserialize, Debug)]
+struct ImageUrl {
+    url: String
+}
+
+#[derive(Deserialize, Debug)]
+struct ImagePage {
+    title: String,
+    #[serde(default)]
+    imageinfo: Vec<ImageUrl>
+}
+
+#[derive(Deserialize, Debug)]
+struct ImagePages {
+    pages: Vec<ImagePage>
+}
+
+#[derive(Deserialize, Debug)]
+struct TitlePage {
+    title: String,
+    #[serde(default)]
+    missing: bool,
+    //files from a shared repository like commons are missing here but still there
+    #[serde(default)]
+    known: bool
+}
+
+#[derive(Deserialize, Debug)]
+struct Normalized {
+    from: String,
+    to: String
+}
+
+//interwiki titles come back in their own list and count as existing
+#[derive(Deserialize, Debug)]
+struct TitlePages {
+    #[serde(default)]
+    pages: Vec<TitlePage>,
+    #[serde(default)]
+    normalized: Vec<Normalized>
+}
+
+#[derive(Deserialize, Debug)]
+struct UploadRes {
+    result: String,
+    warnings: Option<HashMap<String, serde_json::Value>>
+}
+
+#[derive(Deserialize, Debug)]
+struct UploadInfo {
+    upload: UploadRes
+}
+
+#[derive(Debug)]
+pub enum Upload {
+    Done,
+    //file was identical to the current version
+    Unchanged,
+    //not uploaded, warning code -> details
+    Warned(HashMap<String, serde_json::Value>)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiErr {code: String, info: String}
+
+#[derive(Fail, Debug)]
+pub enum MwErr {
+    #[fail(display = "Page is protected: {}", _0)]
+    Protected(String),
+    #[fail(display = "Blocked from editing: {}", _0)]
+    Blocked(String),
+    #[fail(display = "Edit token was rejected: {}", _0)]
+    BadToken(String),
+    #[fail(display = "Rate limited: {}", _0)]
+    RateLimited(String),
+    #[fail(display = "Wiki is read-only: {}", _0)]
+    ReadOnly(String),
+    #[fail(display = "Wiki is lagging: {}", _0)]
+    Lagged(String),
+    #[fail(display = "Not logged in: {}", _0)]
+    NotLoggedIn(String),
+    #[fail(display = "Edit was refused ({})", _0)]
+    EditFailed(String),
+    #[fail(display = "{} was changed by {} at {} (revision {}) since it was last packed", title, user, timestamp, revid)]
+    EditConflict {title: String, user: String, timestamp: String, revid: u64},
+    #[fail(display = "Api Error: {}. {}", code, info)]
+    Other {code: String, info: String}
+}
+
+impl From<ApiErr> for MwErr {
+    fn from(x: ApiErr) -> Self {
+        match x.code.as_str() {
+            "protectedpage" | "cascadeprotected" | "protectednamespace" | "protectedtitle" | "permissiondenied" => MwErr::Protected(x.info),
+            "blocked" | "autoblocked" => MwErr::Blocked(x.info),
+            "badtoken" => MwErr::BadToken(x.info),
+            "ratelimited" => MwErr::RateLimited(x.info),
+            "readonly" => MwErr::ReadOnly(x.info),
+            "maxlag" => MwErr::Lagged(x.info),
+            "assertuserfailed" | "assertnameduserfailed" | "readapidenied" => MwErr::NotLoggedIn(x.info),
+            _ => MwErr::Other {code: x.code, info: x.info}
+        }
+    }
+}
+
+pub fn describe_upload_warning(code: &str, v: &serde_json::Value) -> String {
+    let names = match v {
+        serde_json::Value::Array(x) => x.iter().filter_map(|x| x.as_str()).map(|x| format!("File:{}", x)).collect::<Vec<_>>().join(", "),
+        serde_json::Value::String(x) => x.clone(),
+        x => x.to_string()
+    };
+
+    match code {
+        "exists" => "a different file with this name already exists".to_owned(),
+        "duplicate" => format!("identical to {}", names),
+        "duplicate-archive" => format!("identical to the deleted file {}", names),
+        "was-deleted" => "a file with this name was deleted".to_owned(),
+        "exists-normalized" => format!("a file named {} already exists", names),
+        "badfilename" => format!("invalid file name, would be {}", names),
+        x => format!("{} ({})", x, names)
+    }
+}
+
+//formatversion 1 puts the text under "*"
+fn warning_text(v: &serde_json::Value) -> String {
+    v.get("*").or_else(|| v.get("warnings")).and_then(|x| x.as_str()).map(|x| x.to_owned())
+        .unwrap_or_else(|| v.to_string())
+}
+
+//errors worth waiting out instead of giving up
+pub fn transient(err: &failure::Error) -> bool {
+    if let Some(x) = err.downcast_ref::<MwErr>() {
+        return match x {
+            MwErr::RateLimited(_) | MwErr::ReadOnly(_) | MwErr::Lagged(_) => true,
+            _ => false
+        };
+    }
+
+    if let Some(x) = err.downcast_ref::<std::io::Error>() {
+        return dropped(x);
+    }
+
+    match err.downcast_ref::<reqwest::Error>() {
+        Some(x) => x.is_http() || x.is_timeout() || x.is_server_error() || x.status() == Some(StatusCode::TOO_MANY_REQUESTS) || dropped(x),
+        None => false
+    }
+}
+
+//timeouts and reset connections, which reqwest keeps as the i/o error underneath
+fn dropped(err: &(dyn std::error::Error + 'static)) -> bool {
+    use std::io::ErrorKind::*;
+    let mut cause = Some(err);
+    while let Some(x) = cause {
+        if let Some(x) = x.downcast_ref::<std::io::Error>() {
+            return matches!(x.kind(), TimedOut | ConnectionReset | ConnectionAborted | BrokenPipe | UnexpectedEof | Interrupted);
+        }
+
+        cause = x.source();
+    }
+
+    false
+}
+
+fn retry_after(res: &Response) -> Option<Duration> {
+    res.headers().get(header::RETRY_AFTER)?.to_str().ok()?.trim().parse().ok().map(Duration::from_secs)
+}
+
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(2u64.saturating_pow(attempt).min(MAX_BACKOFF))
+}
+
+fn parse_response(res: Response) -> Res<serde_json::Value> {
+    let mut res = res.error_for_status()?;
+    let v: serde_json::Value = res.json()?;
+
+    if let Some(x) = v.get("error") {
+        let err: ApiErr = serde_json::from_value(x.clone())?;
+        return Err(MwErr::from(err).into());
+    }
+
+    if let Some(serde_json::Value::Object(x)) = v.get("warnings") {
+        for (module, w) in x {
+            warn!("Wiki warning from {}: {}", module, warning_text(w));
+        }
+    }
+
+    Ok(v)
 }
 
 impl Action {
-    pub fn send(self, client: &Client, headers: header::HeaderMap) -> Res<Response> {
-        let params = |x| { Params {action: x, format: "json".to_owned()} };
+    //writes assert a logged in user, so an expired session fails instead of editing anonymously
+    fn writes(&self) -> bool {
+        match self {
+            Action::EditArticle {..} | Action::Upload {..} | Action::Delete {..} | Action::Move {..} => true,
+            _ => false
+        }
+    }
+
+    fn set_token(&mut self, new: &str) {
+        match self {
+            Action::EditArticle {token, ..} | Action::Upload {token, ..} | Action::Delete {token, ..} | Action::Move {token, ..} =>
+                *token = new.to_owned(),
+            _ => ()
+        }
+    }
+
+    pub fn send(self, client: &Client, api: &str, headers: header::HeaderMap, maxlag: Option<u32>) -> Res<Response> {
+        let assert = if self.writes() { Some("user".to_owned()) } else { None };
+        let params = |x| { Params {action: x, format: "json".to_owned(), assert: assert.clone(), maxlag} };
         let res = match self {
-            x @ Action::Query {..} => client.get(API).query(&params(x)).headers(headers).send()?,
-            Action::Upload {filename, filepath, token} => {
-                let form = multipart::Form::new()
-                    .text("action", "upload").file("file", filepath)?
-                    .text("filename", filename).text("token", token);
+            x @ Action::Query {..} | x @ Action::RevQuery {..} | x @ Action::AllPages {..} | x @ Action::ImageInfo {..} | x @ Action::Titles {..} => client.get(api).query(&params(x)).headers(headers).send()?,
+            Action::Upload {filename, filepath, ignorewarnings, token} => {
+                let mut form = multipart::Form::new()
+                    .text("action", "upload").text("format", "json").file("file", filepath)?
+                    .text("filename", filename).text("token", token).text("assert", "user");
 
-                client.post(API).multipart(form).headers(headers).send()?
+                if ignorewarnings {
+                    form = form.text("ignorewarnings", "1");
+                }
+
+                if let Some(x) = maxlag {
+                    form = form.text("maxlag", x.to_string());
+                }
+
+                client.post(api).multipart(form).headers(headers).send()?
             }
-            x => client.post(API).form(&params(x)).headers(headers).send()?
+            x => client.post(api).form(&params(x)).headers(headers).send()?
         };
 
         Ok(res)
     }
 }
 
-fn get_cookies(vec: &mut HashMap<String, String>, resp: &Response) -> Res<()> {
-    for (name, v) in resp.headers().iter() {
-        if name == "set-cookie" {
-            for set in v.to_str()?.to_owned().split(';') {
-                let setv: Vec<&str> = set.split('=').collect();
-                let k = setv.get(0).ok_or(format_err!("No cookie key found!"))?;
-                let v = setv.get(1).unwrap_or(&"true");
-                vec.insert(k.to_owned().trim().to_owned(), v.to_owned().trim().to_owned());
+fn get_cookies(jar: &mut CookieStore, resp: &Response) {
+    for v in resp.headers().get_all(header::SET_COOKIE).iter() {
+        if let Ok(x) = v.to_str() {
+            if let Err(x) = jar.parse(x, resp.url()) {
+                debug!("Ignoring cookie from {}: {:?}", resp.url(), x);
             }
         }
     }
+}
+
+fn load_session(path: &Path) -> Res<CookieStore> {
+    //older versions left it readable by everyone
+    #[cfg(unix)]
+    fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
 
-    Ok(())
+    Ok(CookieStore::load_json(BufReader::new(fs::File::open(path)?))?)
+}
+
+//session cookies are kept too, unlike CookieStore::save_json
+fn session_json(cookies: &CookieStore) -> Res<Vec<u8>> {
+    let mut lines = Vec::new();
+    for cookie in cookies.iter_unexpired() {
+        lines.push(serde_json::to_string(cookie)?);
+    }
+
+    //the store is a hashmap
+    lines.sort();
+    Ok(lines.into_iter().map(|x| x + "\n").collect::<String>().into_bytes())
+}
+
+fn same_user(a: &str, b: &str) -> bool {
+    let norm = |x: &str| x.split('@').next().unwrap_or("").replace('_', " ").to_lowercase();
+    norm(a) == norm(b)
 }
 
 fn parse_token(s: String) -> String {
@@ -144,39 +475,112 @@ fn parse_token(s: String) -> String {
 }
 
 impl MwClient {
-    pub fn new() -> Res<Self> {
+    pub fn new(api: String, session: Option<PathBuf>) -> Res<Self> {
         let client = Client::new();
-        let mut client = MwClient { client, cookies: HashMap::new(), token: "".to_owned() };
+        let cookies = session.as_ref().filter(|x| x.exists()).map(|x| load_session(x).unwrap_or_else(|err| {
+            warn!("Discarding unreadable session {}: {}", x.display(), err);
+            CookieStore::default()
+        })).unwrap_or_default();
+
+        let url = Url::parse(&api)?;
+        let saved = session_json(&cookies)?;
+        Ok(MwClient { client, api, url, bearer: None, cookies, session, saved, creds: None,
+            retries: DEFAULT_RETRIES, maxlag: Some(DEFAULT_MAXLAG), token: "".to_owned() })
+    }
 
-        let parms = Action::Query { meta: "tokens".to_owned(), type_: Some("login".to_owned()) };
-        let res = client.do_action::<Query<TokenQuery<LoginToken>>>(parms)?;
-        client.token = parse_token(res.query.tokens.logintoken);
+    pub fn api(&self) -> &str {
+        &self.api
+    }
 
-        Ok(client)
+    fn save_session(&mut self) -> Res<()> {
+        let path = match &self.session {
+            Some(x) => x,
+            None => return Ok(())
+        };
+
+        let out = session_json(&self.cookies)?;
+        if out == self.saved {
+            return Ok(());
+        }
+
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut opts = fs::OpenOptions::new();
+        opts.write(true).create(true).truncate(true);
+
+        //the cookies log in as the user, so nobody else may read them
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+
+        opts.open(path)?.write_all(&out)?;
+        self.saved = out;
+        Ok(())
     }
 
-    fn do_action_req(&mut self, action: Action) -> Res<Response> {
+    fn send(&mut self, action: Action) -> Res<Response> {
+        let cookies: Vec<String> = self.cookies.get_request_cookies(&self.url).map(|x| format!("{}={}", x.name(), x.value())).collect();
+
         let mut headers = header::HeaderMap::new();
-        let cookies: Vec<String> = self.cookies.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
-        headers.insert("cookie", header::HeaderValue::from_str(&cookies.join("; "))?);
+        headers.insert(header::COOKIE, header::HeaderValue::from_str(&cookies.join("; "))?);
 
-        let res = self.client.request(Method::OPTIONS, API).query(&Preflight {origin: "*".to_owned()}).send()?;
-        get_cookies(&mut self.cookies, &res)?;
-        res.error_for_status()?;
+        if let Some(x) = &self.bearer {
+            headers.insert(header::AUTHORIZATION, header::HeaderValue::from_str(&format!("Bearer {}", x))?);
+        }
 
-        let res = action.send(&self.client, headers)?;
+        let res = action.send(&self.client, &self.api, headers, self.maxlag)?;
+        get_cookies(&mut self.cookies, &res);
+        self.save_session()?;
 
-        if let Some(x) = res.headers().get("mediawiki-api-error") {
-            return Err(format_err!("Mediawiki Api Error: {}", x.to_str()?));
-        }
+        Ok(res)
+    }
+
+    fn do_action_req(&mut self, mut action: Action) -> Res<serde_json::Value> {
+        let mut attempt = 0;
+        let mut relogged = false;
+
+        loop {
+            let (err, wait) = match self.send(action.clone()) {
+                Ok(res) => {
+                    let wait = retry_after(&res);
+                    match parse_response(res) {
+                        Ok(x) => return Ok(x),
+                        Err(x) => (x, wait)
+                    }
+                },
+                Err(x) => (x, None)
+            };
+
+            match err.downcast_ref::<MwErr>() {
+                Some(MwErr::NotLoggedIn(_)) if self.creds.is_some() && !relogged => {
+                    if action.writes() {
+                        warn!("Session expired, logging in again...");
+                    } else {
+                        info!("{} needs a login to read, logging in...", self.api);
+                    }
+
+                    self.relogin()?;
+                    action.set_token(&self.token);
+                    relogged = true;
+                    continue;
+                },
+                Some(MwErr::BadToken(_)) if action.writes() && attempt < self.retries => {
+                    warn!("Edit token expired, fetching a new one...");
+                    self.token = self.get_edit_token()?;
+                    action.set_token(&self.token);
+                },
+                _ if transient(&err) && attempt < self.retries => {
+                    let wait = wait.unwrap_or_else(|| backoff(attempt));
+                    warn!("{} (retrying in {}s, {}/{})", err, wait.as_secs(), attempt + 1, self.retries);
+                    std::thread::sleep(wait);
+                },
+                _ => return Err(err)
+            }
 
-        get_cookies(&mut self.cookies, &res)?;
-        Ok(res.error_for_status()?)
+            attempt += 1;
+        }
     }
 
     fn do_action<T: serde::de::DeserializeOwned>(&mut self, action: Action) -> Res<T> {
-        let mut res = self.do_action_req(action)?;
-        Ok(res.json()?)
+        Ok(serde_json::from_value(self.do_action_req(action)?)?)
     }
 
     pub fn get_edit_token(&mut self) -> Res<String> {
@@ -186,8 +590,47 @@ impl MwClient {
         Ok(parse_token(res.query.tokens.csrftoken))
     }
 
-    pub fn login(&mut self, user: String, pass: String) -> Res<()> {
-        let parms = Action::Login { lgtoken: self.token.clone(), lgname: user, lgpassword: pass };
+    fn get_login_token(&mut self) -> Res<String> {
+        let parms = Action::Query { meta: "tokens".to_owned(), type_: Some("login".to_owned()) };
+        let res = self.do_action::<Query<TokenQuery<LoginToken>>>(parms)?;
+
+        Ok(parse_token(res.query.tokens.logintoken))
+    }
+
+    pub fn set_login(&mut self, user: String, get_password: Box<dyn Fn() -> Res<String>>) {
+        self.creds = Some(Creds { user, password: None, get_password });
+    }
+
+    //picks up a saved session if it still belongs to the login user
+    pub fn resume(&mut self) -> Res<bool> {
+        let user = self.creds.as_ref().map(|x| x.user.clone()).ok_or(format_err!("No login set!"))?;
+        if self.cookies.iter_unexpired().next().is_none() {
+            return Ok(false);
+        }
+
+        let ui = self.user_info()?;
+        if ui.id == 0 || !same_user(&ui.name, &user) {
+            debug!("Saved session is not logged in as {}", user);
+            return Ok(false);
+        }
+
+        self.token = self.get_edit_token()?;
+        info!("Resumed session as {}", ui.name);
+
+        Ok(true)
+    }
+
+    pub fn login(&mut self) -> Res<()> {
+        let (user, pass) = {
+            let creds = self.creds.as_mut().ok_or(format_err!("No login set!"))?;
+            if creds.password.is_none() {
+                creds.password = Some((creds.get_password)()?);
+            }
+
+            (creds.user.clone(), creds.password.clone().unwrap())
+        };
+
+        let parms = Action::Login { lgtoken: self.get_login_token()?, lgname: user, lgpassword: pass };
         let l = self.do_action::<Login>(parms)?.login;
 
         if l.result != "Success" {
@@ -199,6 +642,25 @@ impl MwClient {
         Ok(())
     }
 
+    fn relogin(&mut self) -> Res<()> {
+        self.cookies.clear();
+        self.login()
+    }
+
+    pub fn oauth(&mut self, access_token: String) -> Res<()> {
+        self.bearer = Some(access_token);
+
+        let user = self.user_info()?;
+        if user.id == 0 {
+            return Err(format_err!("OAuth token was not accepted by {}!", self.api));
+        }
+
+        info!("Authenticated as {}", user.name);
+        self.token = self.get_edit_token()?;
+
+        Ok(())
+    }
+
     pub fn token_check(&mut self) -> Res<String> {
         let x = self.do_action::<CheckToken>(Action::CheckToken {token: self.token.clone(), type_: "csrf".to_owned()})?;
         Ok(x.checktoken.result)
@@ -209,15 +671,155 @@ impl MwClient {
         Ok(ui.query.userinfo)
     }
 
-    pub fn edit_article(&mut self, a: MwArticle) -> Res<()> {
-        let parms = Action::EditArticle {article: a, bot: true, token: self.token.clone()};
+    pub fn latest_revision(&mut self, title: &str) -> Res<Option<PageRev>> {
+        let parms = Action::RevQuery {
+            prop: "revisions".to_owned(), titles: title.to_owned(), rvprop: "ids|timestamp|user".to_owned(), rvslots: None, formatversion: 2
+        };
+
+        let res = self.do_action::<Query<RevPages>>(parms)?;
+        Ok(res.query.pages.into_iter().next().and_then(|x| x.revisions.into_iter().next()))
+    }
+
+    //title -> latest revision with its text, missing pages are left out
+    pub fn page_contents(&mut self, titles: &[String]) -> Res<Vec<(String, PageRev)>> {
+        let mut pages = Vec::new();
+
+        for batch in titles.chunks(QUERY_BATCH) {
+            let parms = Action::RevQuery {
+                prop: "revisions".to_owned(), titles: batch.join("|"), rvprop: "ids|timestamp|user|content".to_owned(),
+                rvslots: Some("main".to_owned()), formatversion: 2
+            };
+
+            let res = self.do_action::<Query<RevPages>>(parms)?;
+            pages.extend(res.query.pages.into_iter().filter_map(|x| {
+                let title = x.title;
+                x.revisions.into_iter().next().map(|rev| (title, rev))
+            }));
+        }
+
+        Ok(pages)
+    }
+
+    //pages in the main namespace starting with prefix
+    pub fn prefixed_pages(&mut self, prefix: &str) -> Res<Vec<String>> {
+        let mut titles = Vec::new();
+        let mut apcontinue = None;
+
+        loop {
+            let parms = Action::AllPages { list: "allpages".to_owned(), apprefix: prefix.to_owned(), aplimit: "max".to_owned(), apcontinue };
+            let res = self.do_action::<AllPagesRes>(parms)?;
+            titles.extend(res.query.allpages.into_iter().map(|x| x.title));
+
+            match res.cont {
+                Some(x) => apcontinue = Some(x.apcontinue),
+                None => return Ok(titles)
+            }
+        }
+    }
+
+    //file name without the File: prefix -> download url
+    pub fn image_urls(&mut self, names: &[String]) -> Res<HashMap<String, String>> {
+        let mut urls = HashMap::new();
+
+        for batch in names.chunks(QUERY_BATCH) {
+            let titles: Vec<String> = batch.iter().map(|x| format!("File:{}", x)).collect();
+            let parms = Action::ImageInfo { prop: "imageinfo".to_owned(), titles: titles.join("|"), iiprop: "url".to_owned(), formatversion: 2 };
+
+            let res = self.do_action::<Query<ImagePages>>(parms)?;
+            for page in res.query.pages {
+                if let Some(x) = page.imageinfo.into_iter().next() {
+                    urls.insert(page.title.trim_start_matches("File:").to_owned(), x.url);
+                }
+            }
+        }
+
+        Ok(urls)
+    }
+
+    //titles that don't exist on the wiki, as they were given
+    pub fn missing_pages(&mut self, titles: &[String]) -> Res<Vec<String>> {
+        let mut missing = Vec::new();
+
+        for batch in titles.chunks(QUERY_BATCH) {
+            let res = self.do_action::<Query<TitlePages>>(Action::Titles { titles: batch.join("|"), formatversion: 2 })?;
+            let given: HashMap<String, String> = res.query.normalized.into_iter().map(|x| (x.to, x.from)).collect();
+
+            missing.extend(res.query.pages.into_iter().filter(|x| x.missing && !x.known)
+                .map(|x| given.get(&x.title).cloned().unwrap_or(x.title)));
+        }
+
+        Ok(missing)
+    }
+
+    pub fn download(&self, url: &str) -> Res<Vec<u8>> {
+        let mut res = self.client.get(self.url.join(url)?).send()?.error_for_status()?;
+        let mut data = Vec::new();
+        res.read_to_end(&mut data)?;
+
+        Ok(data)
+    }
+
+    //None if the text was already up to date
+    pub fn edit_article(&mut self, a: MwArticle, base: Option<&Revision>) -> Res<Option<Revision>> {
+        let title = a.title.clone();
+        let parms = Action::EditArticle {
+            article: a, bot: true, basetimestamp: base.map(|x| x.timestamp.clone()), baserevid: base.map(|x| x.revid),
+            token: self.token.clone()
+        };
+
+        let res = match self.do_action::<Edit>(parms) {
+            Ok(x) => x.edit,
+            Err(x) => return match x.downcast::<MwErr>() {
+                Ok(MwErr::Other {ref code, ..}) if code == "editconflict" => {
+                    let rev = self.latest_revision(&title)?.ok_or(format_err!("Edit conflict on {}, which no longer exists!", title))?;
+                    Err(MwErr::EditConflict {title, user: rev.user, timestamp: rev.timestamp, revid: rev.revid}.into())
+                },
+                Ok(x) => Err(x.into()),
+                Err(x) => Err(x)
+            }
+        };
+
+        //captchas, abuse filters and the like
+        if res.result != "Success" {
+            return Err(MwErr::EditFailed(serde_json::to_string(&res.other)?).into());
+        }
+
+        let revid = res.other.get("newrevid").and_then(|x| x.as_u64());
+        let timestamp = res.other.get("newtimestamp").and_then(|x| x.as_str());
+        Ok(match (revid, timestamp) {
+            (Some(revid), Some(timestamp)) => Some(Revision {revid, timestamp: timestamp.to_owned()}),
+            _ => None
+        })
+    }
+
+    pub fn delete(&mut self, title: &str, reason: &str) -> Res<()> {
+        let parms = Action::Delete {title: title.to_owned(), reason: reason.to_owned(), token: self.token.clone()};
         self.do_action_req(parms)?;
         Ok(())
     }
 
-    pub fn upload(&mut self, filename: String, filepath: PathBuf) -> Res<()> {
-        let parms = Action::Upload {filename, filepath, token: self.token.clone()};
+    //keeps the history and leaves a redirect behind
+    pub fn move_page(&mut self, from: &str, to: &str, reason: &str) -> Res<()> {
+        let parms = Action::Move {from: from.to_owned(), to: to.to_owned(), reason: reason.to_owned(), movetalk: true, token: self.token.clone()};
         self.do_action_req(parms)?;
         Ok(())
     }
+
+    pub fn upload(&mut self, filename: String, filepath: PathBuf, ignorewarnings: bool) -> Res<Upload> {
+        let parms = Action::Upload {filename, filepath, ignorewarnings, token: self.token.clone()};
+        let res = match self.do_action::<UploadInfo>(parms) {
+            Ok(x) => x.upload,
+            Err(x) => return match x.downcast::<MwErr>() {
+                Ok(MwErr::Other {ref code, ..}) if code == "fileexists-no-change" => Ok(Upload::Unchanged),
+                Ok(x) => Err(x.into()),
+                Err(x) => Err(x)
+            }
+        };
+
+        match res.warnings {
+            Some(ref x) if x.contains_key("nochange") => Ok(Upload::Unchanged),
+            Some(x) if res.result != "Success" => Ok(Upload::Warned(x)),
+            _ => Ok(Upload::Done)
+        }
+    }
 }
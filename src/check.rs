@@ -0,0 +1,111 @@
+use super::*;
+use std::fmt;
+
+//a link or file in the generated wikitext that goes nowhere
+pub struct Broken {
+    pub file: PathBuf,
+    //in the markdown, when it could be found
+    pub line: Option<usize>,
+    pub target: String,
+    pub reason: &'static str
+}
+
+impl fmt::Display for Broken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {} {}", self.file.display(), line, self.reason, self.target),
+            None => write!(f, "{}: {} {}", self.file.display(), self.reason, self.target)
+        }
+    }
+}
+
+//the wiki only cares about case after the first letter
+pub fn same_title(a: &str, b: &str) -> bool {
+    let norm = |x: &str| {
+        let x = x.replace('_', " ");
+        let mut chars = x.trim().chars();
+        chars.next().map(|c| c.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+    };
+
+    norm(a) == norm(b)
+}
+
+//first line of the markdown mentioning the link, wikitext lines don't match up with it
+fn source_line(source: &str, needles: &[&str]) -> Option<usize> {
+    source.lines().position(|line| needles.iter().any(|x| !x.is_empty() && line.contains(x))).map(|x| x + 1)
+}
+
+//thumb is generated on pack so it counts as present
+pub fn check_links(dir: &PathBuf, title: &str, pages: &[Page], thumb: &Path, client: Option<&mut MwClient>) -> Res<Vec<Broken>> {
+    let link_regex = Regex::new(r"\[\[([^\]|]*)(\|[^\]]*)?\]\]")?;
+    let prefix = section(title, "");
+
+    let mut broken = Vec::new();
+    //checked on the wiki if asked, otherwise assumed fine
+    let mut remote: Vec<(PathBuf, Option<usize>, String, &'static str)> = Vec::new();
+
+    for page in pages {
+        let path = dir.with(&page.rel);
+        let source = fs::read_to_string(&path).unwrap_or_default();
+
+        for image in &page.images {
+            let line = source_line(&source, &[&image.source]);
+            if !image.local {
+                remote.push((path.clone(), line, format!("File:{}", image.name), "no such wiki file"));
+            } else if !image.path.exists() && image.path != thumb {
+                broken.push(Broken {file: path.clone(), line, target: image.source.clone(), reason: "missing image"});
+            }
+        }
+
+        for x in link_regex.captures_iter(&page.text) {
+            let target = x[1].trim().trim_start_matches(':');
+            let label = x.get(2).map_or("", |y| y.as_str().trim_start_matches('|'));
+            let line = source_line(&source, &[target, label]);
+            let lower = target.to_lowercase();
+
+            let page = target.split('#').next().unwrap_or("").trim();
+            //images were checked above
+            if page.is_empty() || lower.starts_with("category:") || lower.starts_with("file:") || lower.starts_with("image:") {
+                continue;
+            }
+
+            if pages.iter().any(|x| same_title(&x.title, page)) {
+                continue;
+            }
+
+            if same_title(page, title) || page.starts_with(&prefix) {
+                broken.push(Broken {file: path.clone(), line, target: page.to_owned(), reason: "no such section"});
+            } else {
+                remote.push((path.clone(), line, page.to_owned(), "no such wiki page"));
+            }
+        }
+    }
+
+    if let Some(client) = client {
+        let mut titles: Vec<String> = remote.iter().map(|(_, _, x, _)| x.clone()).collect();
+        titles.sort();
+        titles.dedup();
+
+        info!("Checking {} page(s) on the wiki...", titles.len());
+        let missing = client.missing_pages(&titles)?;
+
+        broken.extend(remote.into_iter().filter(|(_, _, x, _)| missing.contains(x))
+            .map(|(file, line, target, reason)| Broken {file, line, target, reason}));
+    } else {
+        debug!("Not checking {} link(s) to other wiki pages and files", remote.len());
+    }
+
+    Ok(broken)
+}
+
+pub fn report_broken(broken: &[Broken]) -> Res<()> {
+    for x in broken {
+        error!("{}", x);
+    }
+
+    if broken.is_empty() {
+        Ok(())
+    } else {
+        Err(format_err!("Found {} broken link(s)!", broken.len()))
+    }
+}
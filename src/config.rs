@@ -0,0 +1,294 @@
+use super::*;
+
+pub const CFG_FILE: &str = "bibanon_packer.toml";
+pub const DEFAULT_PROFILE: &str = "default";
+pub const AUTH_METHODS: &[&str] = &["login", "bot", "oauth"];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Auth {
+    //main account password, deprecated by mediawiki
+    Login,
+    //Special:BotPasswords, logs in as user@bot
+    Bot,
+    //owner-only consumer access token
+    OAuth
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Auth::Login
+    }
+}
+
+impl FromStr for Auth {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Res<Self> {
+        match s {
+            "login" => Ok(Auth::Login),
+            "bot" => Ok(Auth::Bot),
+            "oauth" => Ok(Auth::OAuth),
+            x => Err(format_err!("Unknown auth method {}! Expected one of {}", x, AUTH_METHODS.join(", ")))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Profile {
+    #[serde(skip)]
+    pub name: String,
+    pub username: String,
+    //plaintext, only read from old configs
+    pub password: Option<String>,
+    pub password_env: Option<String>,
+    pub password_command: Option<String>,
+    #[serde(default)]
+    pub auth: Auth,
+    pub bot: Option<String>,
+    pub api: Option<String>,
+    pub summary: Option<String>,
+    pub retries: Option<u32>,
+    //0 turns maxlag off
+    pub maxlag: Option<u32>,
+    //tag -> wiki category for every project
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub categories: HashMap<String, String>,
+    pub infobox: Option<Infobox>
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Config {
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>
+}
+
+//config files from before profiles existed
+#[derive(Deserialize)]
+struct LegacyConfig {
+    username: String,
+    password: String,
+    api: Option<String>
+}
+
+fn run_password_command(cmd: &str) -> Res<String> {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let out = std::process::Command::new(shell).arg(flag).arg(cmd)
+        .stdin(std::process::Stdio::inherit()).stderr(std::process::Stdio::inherit()).output()?;
+
+    if !out.status.success() {
+        return Err(format_err!("Password command `{}` failed ({})!", cmd, out.status));
+    }
+
+    Ok(String::from_utf8(out.stdout)?.lines().next().unwrap_or("").to_owned())
+}
+
+impl Profile {
+    fn new(name: &str) -> Self {
+        Profile {
+            name: name.to_owned(), username: String::new(), password: None, password_env: None, password_command: None,
+            auth: Auth::default(), bot: None, api: None, summary: None, retries: None, maxlag: None,
+            categories: HashMap::new(), infobox: None
+        }
+    }
+
+    pub fn password(&self) -> Res<String> {
+        if let Some(var) = &self.password_env {
+            return std::env::var(var).map_err(|_| format_err!("Password variable {} of profile {} is not set!", var, self.name));
+        }
+
+        if let Some(cmd) = &self.password_command {
+            return run_password_command(cmd);
+        }
+
+        if let Some(x) = &self.password {
+            warn!("Profile {} has a plaintext password! Run `credentials {} --password` to encrypt it.", self.name, self.name);
+            return Ok(x.clone());
+        }
+
+        Store::open()?.get(&self.name)
+    }
+
+    pub fn login_name(&self) -> Res<String> {
+        match (self.auth, &self.bot) {
+            (Auth::Bot, _) if self.username.contains('@') => Ok(self.username.clone()),
+            (Auth::Bot, Some(bot)) => Ok(format!("{}@{}", self.username, bot)),
+            (Auth::Bot, None) => Err(format_err!("Profile {} uses a bot password but has no bot name! Set it with `credentials {} --bot`.", self.name, self.name)),
+            _ => Ok(self.username.clone())
+        }
+    }
+
+    fn password_source(&self) -> String {
+        match (&self.password_env, &self.password_command, &self.password) {
+            (Some(x), _, _) => format!("${}", x),
+            (_, Some(x), _) => format!("`{}`", x),
+            (_, _, Some(_)) => "plaintext".to_owned(),
+            _ => "store".to_owned()
+        }
+    }
+}
+
+impl Config {
+    pub fn profile(&self, name: Option<&str>) -> Res<&Profile> {
+        let name = match name.or_else(|| self.default.as_ref().map(|x| x.as_str())) {
+            Some(x) => x,
+            None if self.profiles.len() == 1 => self.profiles.keys().next().unwrap().as_str(),
+            None => DEFAULT_PROFILE
+        };
+
+        self.profiles.get(name)
+            .ok_or(format_err!("No profile named {}! Add it with `credentials {}`.", name, name))
+    }
+}
+
+pub fn cfg_path() -> PathBuf {
+    dirs::config_dir().expect("Could not find config directory! Try a more standardized distribution.").with(CFG_FILE)
+}
+
+fn read_cfg(path: &Path) -> Option<Config> {
+    let x = fs::read_to_string(path).ok()?;
+
+    let mut cfg = match toml::from_str::<LegacyConfig>(&x) {
+        Ok(old) => {
+            let mut cfg = Config::default();
+            let profile = Profile { username: old.username, password: Some(old.password), api: old.api, ..Profile::new(DEFAULT_PROFILE) };
+            cfg.profiles.insert(DEFAULT_PROFILE.to_owned(), profile);
+            cfg
+        },
+        Err(_) => toml::from_str(&x).expect("Error reading config; Maybe delete the file to reset to a default configuration?")
+    };
+
+    for (name, profile) in cfg.profiles.iter_mut() {
+        profile.name = name.clone();
+    }
+
+    Some(cfg)
+}
+
+pub fn prompt(msg: &str, default: Option<&str>) -> String {
+    match default {
+        Some(x) => println!("{} [{}]:", msg, x),
+        None => println!("{}:", msg)
+    }
+
+    let mut line = String::new();
+    io::stdin().read_line(&mut line).unwrap();
+
+    match line.trim() {
+        "" => default.unwrap_or("").to_owned(),
+        x => x.to_owned()
+    }
+}
+
+pub fn set_profile(path: &Path, name: &str, args: &clap::ArgMatches) -> Config {
+    let mut cfg = read_cfg(path).unwrap_or_default();
+
+    let new = !cfg.profiles.contains_key(name);
+    let flags = ["username", "password", "password-env", "password-command", "api", "auth", "bot", "summary", "retries", "maxlag", "default"]
+        .iter().any(|x| args.is_present(x));
+
+    let mut profile = cfg.profiles.get(name).cloned().unwrap_or_else(|| Profile::new(name));
+
+    if let Some(x) = args.value_of("api") {
+        profile.api = Some(x.to_owned());
+    }
+
+    if let Some(x) = args.value_of("summary") {
+        profile.summary = Some(x.to_owned());
+    }
+
+    if let Some(x) = args.value_of("retries") {
+        profile.retries = Some(x.parse().expect("Invalid number of retries!"));
+    }
+
+    if let Some(x) = args.value_of("maxlag") {
+        profile.maxlag = Some(x.parse().expect("Invalid maxlag!"));
+    }
+
+    if let Some(x) = args.value_of("auth") {
+        profile.auth = x.parse().expect("Invalid auth method!");
+    }
+
+    if let Some(x) = args.value_of("bot") {
+        profile.bot = Some(x.to_owned());
+        profile.auth = Auth::Bot;
+    }
+
+    match args.value_of("username") {
+        Some(x) => profile.username = x.to_owned(),
+        None if new || !flags => {
+            profile.username = prompt("Please enter your username", Some(profile.username.as_str()).filter(|x| !x.is_empty()));
+        },
+        None => ()
+    }
+
+    //plaintext passwords get moved into the store whenever their profile is touched
+    let mut password = profile.password.take();
+
+    if let Some(x) = args.value_of("password-env") {
+        profile.password_env = Some(x.to_owned());
+        profile.password_command = None;
+    } else if let Some(x) = args.value_of("password-command") {
+        profile.password_command = Some(x.to_owned());
+        profile.password_env = None;
+    } else if new || !flags || args.is_present("password") {
+        match profile.auth {
+            Auth::Login => println!("Please enter your password:"),
+            Auth::Bot => println!("Please enter the bot password from Special:BotPasswords:"),
+            Auth::OAuth => println!("Please enter your OAuth access token:")
+        }
+
+        password = Some(rpassword::read_password().unwrap());
+        profile.password_env = None;
+        profile.password_command = None;
+    }
+
+    if let (Some(x), None, None) = (password, &profile.password_env, &profile.password_command) {
+        let mut store = Store::open().unwrap_or_else(|x| panic!("{}", x));
+        store.set(name, x);
+        store.save().expect("Error writing credential store!");
+    }
+
+    if args.is_present("default") {
+        cfg.default = Some(name.to_owned());
+    }
+
+    cfg.profiles.insert(name.to_owned(), profile);
+    fs::write(path, toml::to_string(&cfg).unwrap()).expect("Error writing config!");
+
+    cfg
+}
+
+pub fn list_profiles() {
+    let cfg = read_cfg(&cfg_path()).unwrap_or_default();
+    let mut names: Vec<&String> = cfg.profiles.keys().collect();
+    names.sort();
+
+    for name in names {
+        let profile = &cfg.profiles[name];
+        let default = if cfg.default.as_ref() == Some(name) { " (default)" } else { "" };
+        println!("{}{}: {} via {:?} ({}) @ {}", name, default, profile.login_name().unwrap_or_else(|_| profile.username.clone()),
+            profile.auth, profile.password_source(),
+            profile.api.as_ref().map(|x| x.as_str()).unwrap_or(DEFAULT_API));
+    }
+}
+
+pub fn get_cfg() -> Config {
+    let path = cfg_path();
+    read_cfg(&path).unwrap_or_else(|| set_profile(&path, DEFAULT_PROFILE, &clap::ArgMatches::default()))
+}
+
+pub fn get_profile(name: Option<&str>) -> Res<Profile> {
+    Ok(get_cfg().profile(name)?.clone())
+}
+
+//for runs that send nothing, which only need the profile's categories and infobox and shouldn't ask for credentials
+pub fn local_profile(name: Option<&str>) -> Res<Profile> {
+    let cfg = read_cfg(&cfg_path()).unwrap_or_default();
+    match cfg.profile(name) {
+        Ok(x) => Ok(x.clone()),
+        Err(_) if name.is_none() => Ok(Profile::new(DEFAULT_PROFILE)),
+        Err(x) => Err(x)
+    }
+}
@@ -0,0 +1,42 @@
+use super::*;
+
+fn print_diff(title: &str, live: &str, local: &str) -> bool {
+    let live = format!("{}\n", live.trim_end());
+    let local = format!("{}\n", local.trim_end());
+
+    let patch = diffy::create_patch(&live, &local);
+    if patch.hunks().is_empty() {
+        return false;
+    }
+
+    //diffy names the sides original/modified, swap in the page
+    let patch = patch.to_string();
+    println!("--- {} (wiki)\n+++ {} (local)", title, title);
+    print!("{}", patch.splitn(3, '\n').nth(2).unwrap_or(""));
+
+    true
+}
+
+//pages are (wiki title, converted wikitext)
+pub fn diff_pages(client: &mut MwClient, title: &str, pages: &[(String, String)]) -> Res<()> {
+    let titles: Vec<String> = pages.iter().map(|(x, _)| x.clone()).collect();
+    let live: HashMap<String, PageRev> = client.page_contents(&titles)?.into_iter().collect();
+
+    let mut changed = 0;
+    for (page, text) in pages {
+        match live.get(page) {
+            Some(rev) => if print_diff(page, rev.text().unwrap_or(""), text) {
+                changed += 1;
+            },
+            None => println!("Only local: {}", page)
+        }
+    }
+
+    let remote = client.prefixed_pages(&section(title, ""))?;
+    for page in remote.iter().filter(|x| !titles.contains(x)) {
+        println!("Only on the wiki: {}", page);
+    }
+
+    info!("{} of {} pages differ from the wiki", changed, live.len());
+    Ok(())
+}
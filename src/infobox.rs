@@ -0,0 +1,45 @@
+use super::*;
+use std::collections::BTreeMap;
+
+pub const INFOBOX_FIELDS: &[&str] = &["title", "summary", "source", "type", "stats", "sub", "thumbnail"];
+
+//template call put at the top of the index page
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Infobox {
+    //without the Template: namespace
+    pub template: String,
+    //template parameter -> one of INFOBOX_FIELDS, sorted so the page only changes with the metadata
+    pub fields: BTreeMap<String, String>
+}
+
+impl Infobox {
+    pub fn uses_thumbnail(&self) -> bool {
+        self.fields.values().any(|x| x == "thumbnail")
+    }
+
+    //thumbnail is the wiki file name of the generated thumbnail
+    pub fn render(&self, meta: &Metadata, thumbnail: &str) -> Res<String> {
+        let mut out = format!("{{{{{}\n", self.template);
+
+        for (param, field) in &self.fields {
+            let value = match field.as_str() {
+                "title" => meta.title.clone(),
+                "summary" => meta.summary.clone(),
+                "source" => meta.source.clone(),
+                "type" => meta.type_.clone(),
+                "stats" => meta.stats.join(", "),
+                "sub" => meta.sub.clone().unwrap_or_default(),
+                "thumbnail" => thumbnail.to_owned(),
+                x => return Err(format_err!("Unknown infobox field {}! Expected one of {}", x, INFOBOX_FIELDS.join(", ")))
+            };
+
+            //a bare | would start the next parameter
+            if !value.is_empty() {
+                out.push_str(&format!("| {} = {}\n", param, value.replace('|', "{{!}}")));
+            }
+        }
+
+        out.push_str("}}");
+        Ok(out)
+    }
+}
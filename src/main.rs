@@ -14,12 +14,18 @@ extern crate imageproc;
 extern crate simplelog;
 
 extern crate rpassword;
+extern crate ring;
+extern crate base64;
 
 extern crate clap;
 extern crate dirs;
 extern crate notify;
 extern crate reqwest;
+extern crate cookie_store;
+extern crate serde_json;
+extern crate diffy;
 extern crate pandoc;
+extern crate pulldown_cmark;
 extern crate rand;
 extern crate regex;
 
@@ -43,13 +49,37 @@ pub use self::api::*;
 pub mod thumb;
 pub use self::thumb::*;
 
-pub type Res<T> = Result<T, Error>;
+pub mod config;
+pub use self::config::*;
 
-#[derive(Serialize, Deserialize)]
-struct Config {
-    username: String,
-    password: String
-}
+pub mod store;
+pub use self::store::*;
+
+pub mod pull;
+pub use self::pull::*;
+
+pub mod diff;
+pub use self::diff::*;
+
+pub mod manifest;
+pub use self::manifest::*;
+
+pub mod queue;
+pub use self::queue::*;
+
+pub mod convert;
+pub use self::convert::*;
+
+pub mod check;
+pub use self::check::*;
+
+pub mod infobox;
+pub use self::infobox::*;
+
+pub mod nav;
+pub use self::nav::*;
+
+pub type Res<T> = Result<T, Error>;
 
 #[derive(Serialize, Deserialize)]
 pub struct Metadata {
@@ -60,12 +90,124 @@ pub struct Metadata {
     type_: String,
     tags: Vec<String>,
     stats: Vec<String>,
-    sub: Option<String>
+    sub: Option<String>,
+    api: Option<String>,
+    //markdown backend, native unless set to pandoc
+    converter: Option<String>,
+    //added to every page along with the tags
+    category: Option<String>,
+    //tag the section pages too, not just the index
+    #[serde(default)]
+    section_categories: bool,
+    //navigation box on every page and a table of contents on the index
+    #[serde(default)]
+    nav: bool,
+    //section names, the rest follow by numeric prefix and name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    order: Vec<String>,
+    //tag -> wiki category, over the profile's
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    categories: HashMap<String, String>,
+    //over the profile's
+    infobox: Option<Infobox>
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct Mod {
-    last_mod: std::time::SystemTime
+pub const REMOVAL_MODES: &[&str] = &["ask", "delete", "redirect", "keep"];
+
+//what happens to wiki pages whose file is gone
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Removal {
+    Ask,
+    Delete,
+    //to the project index
+    Redirect,
+    Keep
+}
+
+impl FromStr for Removal {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Res<Self> {
+        match s {
+            "ask" => Ok(Removal::Ask),
+            "delete" | "d" => Ok(Removal::Delete),
+            "redirect" | "r" => Ok(Removal::Redirect),
+            "keep" | "k" => Ok(Removal::Keep),
+            x => Err(format_err!("Unknown removal mode {}! Expected one of {}", x, REMOVAL_MODES.join(", ")))
+        }
+    }
+}
+
+struct Session {
+    profile: Profile,
+    api: Option<String>,
+    client: Option<MwClient>,
+    //overwrite pages that were changed on the wiki
+    force: bool,
+    //print what would be published instead
+    dry_run: bool,
+    //only reads, so it logs in once the wiki asks for it
+    read_only: bool,
+    removed: Removal
+}
+
+impl Session {
+    fn new(profile: Profile, api: Option<String>) -> Self {
+        Session { profile, api, client: None, force: false, dry_run: false, read_only: false, removed: Removal::Ask }
+    }
+
+    //cli flag > meta.toml > profile > default
+    fn endpoint(&self, meta: Option<&Metadata>) -> String {
+        self.api.clone().or_else(|| meta.and_then(|x| x.api.clone())).or_else(|| self.profile.api.clone())
+            .unwrap_or_else(|| DEFAULT_API.to_owned())
+    }
+
+    fn summary(&self, meta: &Metadata) -> String {
+        self.profile.summary.clone().unwrap_or_else(|| meta.summary.clone())
+    }
+
+    fn session_path(&self, api: &str) -> Option<PathBuf> {
+        let name = format!("{}-{}.json", self.profile.name, api.replace(|x: char| !x.is_ascii_alphanumeric(), "_"));
+        dirs::cache_dir().map(|x| x.with(SESSION_DIR).with(name))
+    }
+
+    fn client(&mut self, meta: Option<&Metadata>) -> Res<&mut MwClient> {
+        let api = self.endpoint(meta);
+        self.client_for(api)
+    }
+
+    fn client_for(&mut self, api: String) -> Res<&mut MwClient> {
+        if self.client.as_ref().map_or(true, |x| x.api() != api) {
+            let mut client = MwClient::new(api.clone(), self.session_path(&api))?;
+            client.retries = self.profile.retries.unwrap_or(DEFAULT_RETRIES);
+            client.maxlag = self.profile.maxlag.or(Some(DEFAULT_MAXLAG)).filter(|x| *x > 0);
+
+            match self.profile.auth {
+                //no credentials at all, which is fine for reading a public wiki
+                _ if self.read_only && self.profile.username.is_empty() => (),
+                Auth::Login | Auth::Bot if self.read_only => {
+                    let profile = self.profile.clone();
+                    client.set_login(self.profile.login_name()?, Box::new(move || profile.password()));
+                    client.resume()?;
+                },
+                Auth::OAuth if self.read_only => (),
+                Auth::Login | Auth::Bot => {
+                    let profile = self.profile.clone();
+                    client.set_login(self.profile.login_name()?, Box::new(move || profile.password()));
+
+                    if !client.resume()? {
+                        info!("Logging into {}...", api);
+                        client.login()?;
+                    }
+                },
+                Auth::OAuth => client.oauth(self.profile.password()?)?
+            }
+
+            self.client = Some(client);
+        }
+
+        Ok(self.client.as_mut().unwrap())
+    }
 }
 
 pub trait WithPath {
@@ -90,42 +232,16 @@ fn section(title: &str, section: &str) -> String {
 }
 
 pub const META_FILE: &str = "meta.toml";
-pub const MOD_FILE: &str = "mod.toml";
-
-fn parse_md<T: AsRef<Path>>(path: T) -> Res<(String, Vec<PathBuf>)> {
-    let path = path.as_ref();
-    info!("Processing {}...", path.display());
-
-    let mut p = pandoc::new();
-    p.add_input(&path).set_output(pandoc::OutputKind::Pipe)
-        .add_pandoc_path_hint("C:\\Program Files\\Pandoc")
-        .set_output_format(pandoc::OutputFormat::MediaWiki, vec![]);
-
-    let out = p.execute()?;
-
-    let content = match out {
-        pandoc::PandocOutput::ToBuffer(s) => s, _ => unreachable!("AAAAAAAAAAAAAAAAAAAAAAAAAAAA PANDOKKK")
-    };
-
-    let file_regex = Regex::new(r"\[\[File:(.+?)(\|.+)*\]\]")?;
-    let images: Vec<PathBuf> = file_regex.captures_iter(&content).map(|x| {
-        path.with_file_name(&x[1])
-    }).collect();
+pub const SESSION_DIR: &str = "bibanon_packer";
 
-    Ok((content, images))
-}
 pub const INDEX_FILE: &str = "index.md";
 pub const WATCH_WAIT: u64 = 2;
+//ms to wait for the rest of a debounced burst
+pub const WATCH_BATCH: u64 = 500;
 
-fn modded(modf: &Mod, path: &PathBuf) -> Res<bool> {
-    trace!("Checking mod for {}", path.display());
-    let m = fs::metadata(path)?;
-    Ok(m.modified()? > modf.last_mod)
-}
-
-fn read_dir_sections(modf: &Mod, dir: &PathBuf) -> Res<(Vec<(String, String)>, Vec<PathBuf>)> {
+//sections as (name, wikitext, images they use)
+fn read_dir_sections(dir: &PathBuf, conv: &dyn Converter) -> Res<Vec<(String, String, Vec<Image>)>> {
     let mut sections = Vec::new();
-    let mut images = Vec::new();
 
     trace!("Reading directory {}", dir.display());
     for file in fs::read_dir(&dir)? {
@@ -138,124 +254,484 @@ fn read_dir_sections(modf: &Mod, dir: &PathBuf) -> Res<(Vec<(String, String)>, V
         if !ftype.is_dir() {
             if name_str.ends_with(".md") && name != INDEX_FILE {
                 let path = file.path();
-                let (content, mut simages) = parse_md(&path)?;
-                images.append(&mut simages);
+                let (content, images) = parse_md(&path, conv)?;
+                sections.push((name_str.trim_end_matches(".md").to_owned(), content, images));
+            }
+        } else if ftype.is_dir() && !file.path().with(META_FILE).exists() {
+            let sub = read_dir_sections(&dir.with(name_str.to_string()), conv)?;
+            sub.into_iter().for_each(|(subname, v, images)|
+                sections.push((section(&name_str.to_string(), &subname), v, images)));
+        }
+    }
+
+    Ok(sections)
+}
+
+//one file of the project that needs publishing
+#[derive(Clone)]
+struct Item {
+    rel: String,
+    title: String,
+    hash: String
+}
 
-                if modded(&modf, &path)? {
-                    sections.push((name_str.trim_end_matches(".md").to_owned(), content));
+fn publish(client: &mut MwClient, force: bool, manifest: &mut Manifest, item: Item, article: MwArticle) -> Res<()> {
+    let base = if force { None } else { manifest.base(&item.title) };
+
+    let rev = match client.edit_article(article, base.as_ref()) {
+        Ok(x) => x.or(base),
+        Err(x) => return Err(match x.downcast::<MwErr>() {
+            Ok(x @ MwErr::EditConflict {..}) => format_err!("{}! Merge their changes into your files or pack with --force to overwrite them.", x),
+            Ok(x) => x.into(),
+            Err(x) => x
+        })
+    };
+
+    manifest.record(&item.rel, Entry {
+        hash: item.hash, title: item.title,
+        revid: rev.as_ref().map(|x| x.revid), timestamp: rev.map(|x| x.timestamp)
+    });
+
+    //saved right away so a failed pack picks up where it stopped
+    manifest.save()
+}
+
+fn run_job(client: &mut MwClient, force: bool, manifest: &mut Manifest, item: Item, job: Job) -> Res<()> {
+    let image = match job {
+        Job::Edit {text, summary} => {
+            info!("Uploading {}...", item.title);
+            let article = MwArticle {title: item.title.clone(), text, summary};
+            return publish(client, force, manifest, item, article);
+        },
+        Job::Upload {path} => path
+    };
+
+    info!("Uploading image {}...", image.display());
+    let name = item.title.clone();
+    //published from this file before, anything else under the name isn't ours to overwrite
+    let ours = manifest.get(&item.rel).map_or(false, |x| x.title == item.title);
+
+    match client.upload(name.clone(), image.clone(), false)? {
+        Upload::Done => (),
+        Upload::Unchanged => info!("{} is unchanged on the wiki", name),
+        Upload::Warned(ref x) if ours && x.keys().all(|x| x == "exists") => {
+            info!("Uploading new version of {}...", name);
+            client.upload(name, image, true)?;
+        },
+        Upload::Warned(x) => {
+            for (code, v) in x {
+                warn!("Skipped image {}: {}", name, describe_upload_warning(&code, &v));
+            }
+
+            return Ok(());
+        }
+    }
+
+    manifest.record(&item.rel, Entry {hash: item.hash, title: item.title, revid: None, timestamp: None});
+    manifest.save()
+}
+
+//jobs left over when the wiki went away, for watch to queue
+fn unsent(dir: &PathBuf, api: &str, err: Error, jobs: Vec<(Item, Job)>) -> Error {
+    if !transient(&err) || jobs.is_empty() {
+        return err;
+    }
+
+    let cause = err.to_string();
+    let jobs: Vec<Queued> = jobs.into_iter()
+        .map(|(item, job)| Queued::new(dir, api, item.rel, item.title, item.hash, job, cause.clone())).collect();
+
+    Unsent {count: jobs.len(), cause, jobs}.into()
+}
+
+//sends queued jobs in order, stops at the first one the wiki is still unreachable for
+fn try_flush(sess: &mut Session, queue: &mut Queue) -> Res<usize> {
+    let mut sent = 0;
+
+    while !queue.entries.is_empty() {
+        let x = queue.entries[0].clone();
+        let mut manifest = Manifest::open(&x.project)?;
+
+        if manifest.get(&x.rel).map_or(false, |y| y.hash == x.hash) {
+            debug!("Queued {} was already published", x.title);
+        } else {
+            info!("Sending queued {}...", x.title);
+            let force = sess.force;
+            let item = Item {rel: x.rel.clone(), title: x.title.clone(), hash: x.hash.clone()};
+
+            match sess.client_for(x.api.clone()).and_then(|client| run_job(client, force, &mut manifest, item, x.job.clone())) {
+                Ok(()) => sent += 1,
+                Err(err) => if transient(&err) {
+                    queue.entries[0].error = err.to_string();
+                    queue.save()?;
+                    return Err(err);
+                } else {
+                    error!("Dropped queued {}: {}", x.title, err);
                 }
             }
-        } else if ftype.is_dir() {
-            let (sub, mut simages) = read_dir_sections(modf, &dir.with(name_str.to_string()))?;
-            sub.into_iter().for_each(|(subname, v)|
-                sections.push((section(&name_str.to_string(), &subname), v)));
-            images.append(&mut simages);
         }
+
+        queue.entries.remove(0);
+        queue.save()?;
     }
 
-    Ok((sections, images))
+    Ok(sent)
 }
 
-fn try_proc(cfg: &Config, client: &mut MwClient, dir: &PathBuf) -> Res<()> {
-    debug!("Processing directory {}", dir.display());
-    trace!("Reading meta.toml");
-    let meta: Metadata = toml::from_str(&fs::read_to_string(dir.with(META_FILE))?)?;
-    trace!("Reading mod.toml");
-    let modf: Mod = fs::read_to_string(dir.with(MOD_FILE)).map_err(Error::from)
-        .and_then(|x| Ok(toml::from_str(&x)?))
-        .unwrap_or(Mod {last_mod: std::time::SystemTime::UNIX_EPOCH});
+//everything a pack would publish, worked out without touching the wiki
+struct Plan {
+    //generated but not yet written
+    thumb: Option<(PathBuf, Vec<u8>)>,
+    pages: Vec<(Item, String)>,
+    images: Vec<(Item, PathBuf)>,
+    //renamed files, old path and entry -> new item
+    moves: Vec<(String, Entry, Item)>,
+    //published pages whose file is gone
+    removed: Vec<(String, Entry)>
+}
+
+fn rel_path(dir: &PathBuf, path: &Path) -> String {
+    path.strip_prefix(dir).unwrap_or(path).to_string_lossy().to_string()
+}
+
+fn thumb_name(title: &str) -> String {
+    format!("{}-thumbnail.jpg", title.replace(' ', "-"))
+}
+
+fn thumb_path(dir: &PathBuf, meta: &Metadata) -> PathBuf {
+    dir.with(thumb_name(&meta.title))
+}
+
+//a converted markdown file of the project
+pub struct Page {
+    rel: String,
+    title: String,
+    text: String,
+    images: Vec<Image>
+}
+
+//mapped through meta.toml, then the profile, a tag mapped to "" gets no category
+fn categories(meta: &Metadata, profile: &Profile) -> Vec<String> {
+    let tags = meta.tags.iter().map(|tag| meta.categories.get(tag).or_else(|| profile.categories.get(tag)).unwrap_or(tag));
+
+    let mut categories: Vec<String> = Vec::new();
+    for x in meta.category.iter().chain(tags) {
+        if !x.is_empty() && !categories.contains(x) {
+            categories.push(x.clone());
+        }
+    }
+
+    categories
+}
+
+fn with_categories(text: &str, categories: &[String]) -> String {
+    if categories.is_empty() {
+        return text.to_owned();
+    }
+
+    let links: Vec<String> = categories.iter().map(|x| format!("[[Category:{}]]", x)).collect();
+    format!("{}\n\n{}\n", text.trim_end(), links.join("\n"))
+}
+
+fn read_pages(dir: &PathBuf, meta: &Metadata, profile: &Profile) -> Res<Vec<Page>> {
+    info!("Parsing files...");
+    let conv = converter(meta.converter.as_ref().map(|x| x.as_str()))?;
+    let index_path = dir.with(INDEX_FILE);
+    let (mut index, mut images) = parse_md(&index_path, conv.as_ref())?;
+
+    let mut sections = read_dir_sections(dir, conv.as_ref())?;
+    sort_sections(&mut sections, &meta.order);
+
+    if meta.nav {
+        let names: Vec<String> = sections.iter().map(|(x, _, _)| x.clone()).collect();
+        let parent = match dir.parent().and_then(project_root) {
+            Some(x) => Some(toml::from_str::<Metadata>(&fs::read_to_string(x.with(META_FILE))?)?.title),
+            None => None
+        };
+
+        let nav = nav_box(&meta.title, parent.as_ref().map(|x| x.as_str()), None, names.first().map(|x| x.as_str()));
+        index = format!("{}\n\n{}\n\n{}\n", index.trim_end(), contents(&meta.title, &names), nav);
+
+        for (i, (_, text, _)) in sections.iter_mut().enumerate() {
+            let prev = if i > 0 { names.get(i - 1) } else { None };
+            let nav = nav_box(&meta.title, Some(&meta.title), prev.map(|x| x.as_str()), names.get(i + 1).map(|x| x.as_str()));
+            *text = format!("{}\n\n{}\n", text.trim_end(), nav);
+        }
+    }
+
+    //meta.toml > profile
+    if let Some(infobox) = meta.infobox.as_ref().or(profile.infobox.as_ref()) {
+        let thumb = project_image(&thumb_path(dir, meta))?;
+        index = format!("{}\n\n{}", infobox.render(meta, &thumb.name)?, index);
 
+        if infobox.uses_thumbnail() {
+            images.push(thumb);
+        }
+    }
+
+    let categories = &categories(meta, profile);
+    let section_categories = if meta.section_categories { categories } else { &[][..] };
+    let pages = std::iter::once(Page {rel: INDEX_FILE.to_owned(), title: meta.title.clone(), text: with_categories(&index, categories), images})
+        .chain(sections.into_iter().map(|(name, text, images)| Page {
+            rel: format!("{}.md", name), title: section(&meta.title, &name), text: with_categories(&text, section_categories), images
+        })).collect();
+
+    Ok(pages)
+}
+
+fn make_plan(dir: &PathBuf, meta: &Metadata, profile: &Profile, manifest: &mut Manifest) -> Res<Plan> {
     trace!("Checking for thumbnail");
-    let thumb_name = format!("{}-thumbnail.jpg", meta.title.replace(' ', "-"));
-    if !Path::new(&dir.with(&thumb_name)).exists() {
+    let thumb_path = thumb_path(dir, meta);
+    let mut thumb = None;
+    if !thumb_path.exists() {
         info!("Generating thumbnail... (can take a few seconds)");
         let bg = fs::read(dir.with("bg.jpg")).or_else(|_| fs::read(dir.with("bg.png"))).ok();
-        fs::write(dir.with(&thumb_name), make_thumb(bg, &meta)?)?;
+        thumb = Some((thumb_path.clone(), make_thumb(bg, &meta)?));
     }
 
-    info!("Parsing files...");
-    let index_path = dir.with(INDEX_FILE);
-    let (index, mut images) = parse_md(&index_path)?;
+    let all = read_pages(dir, meta, profile)?;
+    //before anything is sent, so a bad link doesn't leave the wiki half updated
+    report_broken(&check_links(dir, &meta.title, &all, &thumb_path, None)?)?;
 
-    if modded(&modf, &index_path)? {
-        info!("Uploading index...");
-        client.edit_article(MwArticle { title: meta.title.clone(), text: index, summary: meta.summary.clone() })?;
+    let mut pages = Vec::new();
+    let mut images = Vec::new();
+    let mut seen = Vec::new();
+
+    for Page {rel, title, text, images: mut pimages} in all {
+        images.append(&mut pimages);
+        seen.push(rel.clone());
+        let hash = hash(text.as_bytes());
+        if manifest.changed(&rel, &title, &dir.with(&rel), &hash) {
+            pages.push((Item {rel, title, hash}, text));
+        }
     }
 
-    let (sections, mut simages) = read_dir_sections(&modf, dir)?;
-    images.append(&mut simages);
+    let mut plan_images: Vec<(Item, PathBuf)> = Vec::new();
+    let mut names: Vec<(String, String)> = Vec::new();
+    for Image {path: image, name: title, ..} in images.into_iter().filter(|x| x.local) {
+        let rel = rel_path(dir, &image);
+        //the wiki name flattens the path, so two files could end up overwriting each other
+        match names.iter().find(|(x, _)| same_title(x, &title)) {
+            Some((_, other)) if *other != rel => return Err(format_err!("Images {} and {} would both be uploaded as File:{}, rename one of them!", other, rel, title)),
+            Some(_) => (),
+            None => names.push((title.clone(), rel.clone()))
+        }
+
+        let data = match &thumb {
+            Some((path, x)) if *path == image => x.clone(),
+            _ => fs::read(&image).map_err(|_| format_err!("Image {} doesn't exist!", image.display()))?
+        };
 
-    for (name, text) in sections {
-        info!("Uploading {}...", name);
-        client.edit_article(MwArticle {title: section(&meta.title, &name), text, summary: meta.summary.clone()})?;
+        let hash = hash(&data);
+        seen.push(rel.clone());
+
+        if manifest.changed(&rel, &title, &image, &hash) && !plan_images.iter().any(|(x, _)| x.rel == rel) {
+            plan_images.push((Item {rel, title, hash}, image));
+        }
     }
 
-    for image in images {
-        if let Ok(true) = modded(&modf, &image) {
-            info!("Uploading image {}...", image.display());
+    //a new page with the text of a vanished one was renamed
+    let mut moves = Vec::new();
+    let mut removed = Vec::new();
+    for (rel, entry) in manifest.stale(&seen) {
+        if !rel.ends_with(".md") {
+            manifest.forget(&rel);
+            continue;
+        }
 
-            let name = image.file_name().ok_or(format_err!("Invalid path for image {}!", image.display()))?;
-            if image.exists() {
-                client.upload(name.to_string_lossy().to_string(), image)?;
-            } else {
-                return Err(format_err!("Image {} doesn't exist!", image.display()).into())
-            }
+        let renamed = pages.iter().position(|(x, _): &(Item, String)| x.hash == entry.hash && manifest.get(&x.rel).is_none());
+        match renamed {
+            Some(i) => moves.push((rel, entry, pages.remove(i).0)),
+            None => removed.push((rel, entry))
         }
     }
 
-    fs::write(dir.with(MOD_FILE), toml::to_string(&Mod {last_mod: std::time::SystemTime::now()})?)?;
-    info!("Packed & published!");
-    Ok(())
+    Ok(Plan { thumb, pages, images: plan_images, moves, removed })
 }
 
-fn try_watch(cfg: &Config, client: &mut MwClient, dir: &PathBuf, path: PathBuf) -> Res<()> {
-    let path = path.strip_prefix(&dir)?.join(META_FILE);
+fn print_plan(api: &str, manifest: &Manifest, plan: &Plan) {
+    println!("Would publish to {}:", api);
+
+    for (item, text) in &plan.pages {
+        let status = if manifest.get(&item.rel).is_some() || manifest.base(&item.title).is_some() { "changed" } else { "new" };
+        println!("  {:<8} {} ({} bytes)", status, item.title, text.len());
+    }
+
+    for (item, image) in &plan.images {
+        let size = match &plan.thumb {
+            Some((path, x)) if path == image => x.len() as u64,
+            _ => fs::metadata(image).map(|x| x.len()).unwrap_or(0)
+        };
+
+        println!("  {:<8} {} -> File:{} ({} bytes)", "image", image.display(), item.title, size);
+    }
+
+    for (_, old, item) in &plan.moves {
+        println!("  {:<8} {} -> {}", "move", old.title, item.title);
+    }
 
-    for x in path.ancestors() {
-        let x_path = dir.join(x);
-        if x_path.with_file_name(META_FILE).exists() {
-            try_proc(cfg, client, &x_path.parent().unwrap().to_path_buf())?;
+    for (_, entry) in &plan.removed {
+        println!("  {:<8} {} (file is gone)", "removed", entry.title);
+    }
+
+    if plan.pages.is_empty() && plan.images.is_empty() && plan.moves.is_empty() && plan.removed.is_empty() {
+        println!("  nothing, everything is up to date");
+    }
+}
+
+fn try_proc(sess: &mut Session, dir: &PathBuf) -> Res<()> {
+    debug!("Processing directory {}", dir.display());
+    trace!("Reading meta.toml");
+    let meta: Metadata = toml::from_str(&fs::read_to_string(dir.with(META_FILE))?)?;
+    trace!("Reading manifest");
+    let mut manifest = Manifest::open(dir)?;
+
+    let plan = make_plan(dir, &meta, &sess.profile, &mut manifest)?;
+
+    if sess.dry_run {
+        print_plan(&sess.endpoint(Some(&meta)), &manifest, &plan);
+        return Ok(());
+    }
+
+    if let Some((path, thumb)) = &plan.thumb {
+        fs::write(path, thumb)?;
+    }
+
+    let summary = sess.summary(&meta);
+    let (force, removal) = (sess.force, sess.removed);
+    let api = sess.endpoint(Some(&meta));
+
+    let jobs: Vec<(Item, Job)> = plan.pages.into_iter().map(|(item, text)| (item, Job::Edit {text, summary: summary.clone()}))
+        .chain(plan.images.into_iter().map(|(item, path)| (item, Job::Upload {path}))).collect();
+
+    let client = match sess.client(Some(&meta)) {
+        Ok(x) => x,
+        Err(x) => return Err(unsent(dir, &api, x, jobs))
+    };
+
+    for (old_rel, old, item) in plan.moves {
+        info!("Moving {} to {}...", old.title, item.title);
+        client.move_page(&old.title, &item.title, &summary)?;
+
+        let rev = client.latest_revision(&item.title)?.map(|x| x.revision());
+        manifest.forget(&old_rel);
+        manifest.record(&item.rel, Entry {
+            hash: item.hash, title: item.title,
+            revid: rev.as_ref().map(|x| x.revid), timestamp: rev.map(|x| x.timestamp)
+        });
+        manifest.save()?;
+    }
+
+    let mut jobs = jobs.into_iter();
+    while let Some((item, job)) = jobs.next() {
+        if let Err(x) = run_job(client, force, &mut manifest, item.clone(), job.clone()) {
+            return Err(unsent(dir, &api, x, std::iter::once((item, job)).chain(jobs).collect()));
         }
     }
 
+    for (rel, entry) in plan.removed {
+        let removal = match removal {
+            Removal::Ask => prompt(&format!("{} no longer has a file. [d]elete it, [r]edirect it to {} or [k]eep it?", entry.title, meta.title), Some("k"))
+                .parse().unwrap_or(Removal::Keep),
+            x => x
+        };
+
+        match removal {
+            Removal::Delete => {
+                info!("Deleting {}...", entry.title);
+                client.delete(&entry.title, &summary)?;
+            },
+            Removal::Redirect => {
+                info!("Redirecting {} to {}...", entry.title, meta.title);
+                let text = format!("#REDIRECT [[{}]]", meta.title);
+                let base = if force { None } else { entry.revision() };
+                client.edit_article(MwArticle {title: entry.title.clone(), text, summary: summary.clone()}, base.as_ref())?;
+            },
+            _ => info!("Keeping {} on the wiki", entry.title)
+        }
+
+        manifest.forget(&rel);
+        manifest.save()?;
+    }
+
+    manifest.save()?;
+    info!("Packed & published!");
     Ok(())
 }
 
-fn cfg_path() -> PathBuf {
-    dirs::config_dir().expect("Could not find config directory! Try a more standardized distribution.").with("bibanon_packer.toml")
+fn try_diff(sess: &mut Session, dir: &PathBuf) -> Res<()> {
+    let meta: Metadata = toml::from_str(&fs::read_to_string(dir.with(META_FILE))?)?;
+    //every page, not just the ones changed since the last pack
+    let pages: Vec<(String, String)> = read_pages(dir, &meta, &sess.profile)?.into_iter().map(|x| (x.title, x.text)).collect();
+
+    diff_pages(sess.client(Some(&meta))?, &meta.title, &pages)
 }
 
-fn set_cfg(path: PathBuf, default_username: Option<String>) -> Config {
-    let sin = io::stdin();
-
-    let cfg = {
-        let mut username = String::new();
-        match default_username {
-            Some(x) => username = x,
-            None => {
-                println!("Please enter your username:");
-                sin.read_line(&mut username).unwrap();
-                username = username.trim().to_owned();
-            }
-        }
+fn try_check(sess: &mut Session, dir: &PathBuf, remote: bool) -> Res<()> {
+    let meta: Metadata = toml::from_str(&fs::read_to_string(dir.with(META_FILE))?)?;
+    let pages = read_pages(dir, &meta, &sess.profile)?;
 
-        println!("Please enter your password:");
-        let password = rpassword::read_password().unwrap();
+    let client = if remote { Some(sess.client(Some(&meta))?) } else { None };
+    report_broken(&check_links(dir, &meta.title, &pages, &thumb_path(dir, &meta), client)?)?;
 
-        Config { username, password }
-    };
+    info!("No broken links in {}", meta.title);
+    Ok(())
+}
+
+//files the packer writes itself, which must not trigger another pack
+fn generated(path: &Path) -> bool {
+    let name = path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    name == MANIFEST_FILE || name == MOD_FILE || name.ends_with("-thumbnail.jpg")
+}
 
-    fs::write(&path, toml::to_string(&cfg).unwrap()).expect("Error writing cfg.toml");
-    cfg
+//nearest directory with a meta.toml, so nested projects own their files
+//works for deleted paths too, they just never have a meta.toml of their own
+fn project_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors().find(|x| x.join(META_FILE).is_file()).map(|x| x.to_path_buf())
 }
 
-fn get_cfg() -> Config {
-    let cfg_path = cfg_path();
-    if let Ok(x) = fs::read_to_string(&cfg_path) {
-        toml::from_str(&x).expect("Error reading cfg.toml; Maybe delete the file to reset to a default configuration?")
-    } else {
-        set_cfg(cfg_path, None)
+//one pack per project for a burst of debounced events
+fn try_watch(sess: &mut Session, queue: &mut Queue, dir: &PathBuf, paths: Vec<PathBuf>) {
+    let mut paths: Vec<PathBuf> = paths.into_iter().filter(|x| !generated(x)).collect();
+    paths.sort();
+    paths.dedup();
+
+    if paths.is_empty() {
+        return;
+    }
+
+    let mut projects: Vec<PathBuf> = Vec::new();
+    for path in &paths {
+        //never a project above the watched directory
+        match project_root(path).filter(|x| x.starts_with(dir)) {
+            Some(x) => if !projects.contains(&x) { projects.push(x) },
+            None => debug!("{} is not part of a project under {}", path.display(), dir.display())
+        }
+    }
+
+    let mut failed = 0;
+    for project in &projects {
+        match try_proc(sess, project) {
+            //anything it had queued went out with this pack
+            Ok(()) => if !sess.dry_run && queue.discard_project(project) > 0 {
+                queue.save().unwrap_or_else(|x| error!("Error saving queue: {}", x));
+            },
+            Err(x) => {
+                failed += 1;
+                match x.downcast::<Unsent>() {
+                    Ok(x) => {
+                        warn!("{}", x);
+                        x.jobs.into_iter().for_each(|x| queue.push(x));
+                        queue.save().unwrap_or_else(|x| error!("Error saving queue: {}", x));
+                    },
+                    Err(x) => error!("Error updating {}: {}", project.display(), x)
+                }
+            }
+        }
     }
+
+    info!("{} changed file(s), packed {} of {} project(s), {} change(s) queued. Watching for changes...",
+        paths.len(), projects.len() - failed, projects.len(), queue.entries.len());
 }
 
 fn main() {
@@ -272,15 +748,93 @@ fn main() {
             .subcommand(SubCommand::with_name("pack")
                 .about("Pack a directory and upload it.")
                 .arg(Arg::with_name("DIRECTORY")
-                    .index(1).help("Directory to pack")))
+                    .index(1).help("Directory to pack"))
+                .arg(Arg::with_name("api").long("api").takes_value(true)
+                    .help("Wiki api.php endpoint, overrides meta.toml and the profile"))
+                .arg(Arg::with_name("profile").long("profile").short("p").takes_value(true)
+                    .help("Credentials profile to publish with"))
+                .arg(Arg::with_name("force").long("force").short("f")
+                    .help("Overwrite pages that were edited on the wiki since the last pack"))
+                .arg(Arg::with_name("dry-run").long("dry-run").short("n")
+                    .help("Print what would be published without sending anything"))
+                .arg(Arg::with_name("removed").long("removed").takes_value(true).possible_values(REMOVAL_MODES)
+                    .help("What to do with wiki pages whose file was deleted, asks by default")))
             .subcommand(SubCommand::with_name("watch")
                 .about("Watch a directory and upload it.")
                 .arg(Arg::with_name("DIRECTORY")
-                    .index(1).help("Directory to watch")))
+                    .index(1).help("Directory to watch"))
+                .arg(Arg::with_name("api").long("api").takes_value(true)
+                    .help("Wiki api.php endpoint, overrides meta.toml and the profile"))
+                .arg(Arg::with_name("profile").long("profile").short("p").takes_value(true)
+                    .help("Credentials profile to publish with"))
+                .arg(Arg::with_name("force").long("force").short("f")
+                    .help("Overwrite pages that were edited on the wiki since the last pack"))
+                .arg(Arg::with_name("dry-run").long("dry-run").short("n")
+                    .help("Print what would be published without sending anything"))
+                .arg(Arg::with_name("removed").long("removed").takes_value(true).possible_values(REMOVAL_MODES)
+                    .help("What to do with wiki pages whose file was deleted, keeps them by default")))
+            .subcommand(SubCommand::with_name("diff")
+                .about("Show how a directory differs from the live wiki pages.")
+                .arg(Arg::with_name("DIRECTORY")
+                    .index(1).help("Directory to compare"))
+                .arg(Arg::with_name("api").long("api").takes_value(true)
+                    .help("Wiki api.php endpoint, overrides meta.toml and the profile"))
+                .arg(Arg::with_name("profile").long("profile").short("p").takes_value(true)
+                    .help("Credentials profile to read with")))
+            .subcommand(SubCommand::with_name("check")
+                .about("Check a directory for broken links and missing images.")
+                .arg(Arg::with_name("DIRECTORY")
+                    .index(1).help("Directory to check"))
+                .arg(Arg::with_name("remote").long("remote").short("r")
+                    .help("Also check that linked pages outside the project exist on the wiki"))
+                .arg(Arg::with_name("api").long("api").takes_value(true)
+                    .help("Wiki api.php endpoint, overrides meta.toml and the profile"))
+                .arg(Arg::with_name("profile").long("profile").short("p").takes_value(true)
+                    .help("Credentials profile to read with")))
+            .subcommand(SubCommand::with_name("pull")
+                .about("Import a wiki page and its subpages into a directory. Needs pandoc.")
+                .arg(Arg::with_name("TITLE")
+                    .index(1).required(true).help("Page to import"))
+                .arg(Arg::with_name("DIRECTORY")
+                    .index(2).help("Directory to import into"))
+                .arg(Arg::with_name("api").long("api").takes_value(true)
+                    .help("Wiki api.php endpoint, overrides the profile"))
+                .arg(Arg::with_name("profile").long("profile").short("p").takes_value(true)
+                    .help("Credentials profile to read with"))
+                .arg(Arg::with_name("force").long("force").short("f")
+                    .help("Overwrite an existing project")))
+            .subcommand(SubCommand::with_name("queue")
+                .about("Inspect, send or discard changes watch couldn't publish.")
+                .arg(Arg::with_name("flush").long("flush").short("f").help("Send queued changes now"))
+                .arg(Arg::with_name("discard").long("discard").short("d").takes_value(true).multiple(true)
+                    .help("Drop queued changes by their number in the list"))
+                .arg(Arg::with_name("all").long("all").help("Drop every queued change"))
+                .arg(Arg::with_name("profile").long("profile").short("p").takes_value(true)
+                    .help("Credentials profile to publish with")))
             .subcommand(SubCommand::with_name("credentials")
-                .about("Set credentials.")
-                .arg(Arg::with_name("USERNAME")
-                    .index(1).help("Username for wiki")))
+                .about("Add or edit a credentials profile.")
+                .arg(Arg::with_name("PROFILE")
+                    .index(1).help("Profile to add or edit"))
+                .arg(Arg::with_name("list").long("list").short("l").help("List profiles"))
+                .arg(Arg::with_name("username").long("username").takes_value(true).help("Username for wiki"))
+                .arg(Arg::with_name("password").long("password").help("Change the password kept in the encrypted store"))
+                .arg(Arg::with_name("password-env").long("password-env").takes_value(true)
+                    .help("Read the password from this environment variable"))
+                .arg(Arg::with_name("password-command").long("password-command").takes_value(true)
+                    .help("Run this command to print the password"))
+                .arg(Arg::with_name("api").long("api").takes_value(true)
+                    .help("Wiki api.php endpoint"))
+                .arg(Arg::with_name("auth").long("auth").takes_value(true)
+                    .possible_values(AUTH_METHODS).help("Authentication method"))
+                .arg(Arg::with_name("bot").long("bot").takes_value(true)
+                    .help("Bot name from Special:BotPasswords, implies --auth bot"))
+                .arg(Arg::with_name("summary").long("summary").takes_value(true)
+                    .help("Default edit summary"))
+                .arg(Arg::with_name("retries").long("retries").takes_value(true)
+                    .help("How often to retry requests when the wiki is lagging or unreachable"))
+                .arg(Arg::with_name("maxlag").long("maxlag").takes_value(true)
+                    .help("Seconds of database lag to accept before backing off, 0 to disable"))
+                .arg(Arg::with_name("default").long("default").help("Use this profile when none is given")))
             .setting(AppSettings::SubcommandRequiredElseHelp)
             .get_matches();
 
@@ -299,7 +853,15 @@ fn main() {
                 type_: "story".to_owned(),
                 tags: Vec::new(),
                 stats: Vec::new(),
-                sub: None
+                sub: None,
+                api: None,
+                converter: None,
+                category: None,
+                section_categories: false,
+                nav: false,
+                order: Vec::new(),
+                categories: HashMap::new(),
+                infobox: None
             };
 
             fs::write(dir.with(META_FILE), toml::to_string(&default_meta).expect("Error serializing metadata")).expect("Error writing metadata file!");
@@ -308,46 +870,169 @@ fn main() {
             info!("Initialized directory!");
         },
         ("credentials", Some(args)) => {
-            set_cfg(cfg_path(), args.value_of("USERNAME").map(|x| x.to_owned()));
-            info!("Credentials set!");
+            if args.is_present("list") {
+                list_profiles();
+                return;
+            }
+
+            let name = args.value_of("PROFILE").unwrap_or(DEFAULT_PROFILE);
+            set_profile(&cfg_path(), name, args);
+            info!("Credentials set for profile {}!", name);
         },
         ("pack", Some(args)) => {
-            let cfg = get_cfg();
+            let profile = if args.is_present("dry-run") { local_profile(args.value_of("profile")) } else { get_profile(args.value_of("profile")) };
+            let profile = profile.unwrap_or_else(|x| panic!("{}", x));
+
+            let dir = fs::canonicalize(args.value_of("DIRECTORY").unwrap_or("./")).expect("Cannot parse path!");
+
+            let mut sess = Session::new(profile, args.value_of("api").map(|x| x.to_owned()));
+            sess.force = args.is_present("force");
+            sess.dry_run = args.is_present("dry-run");
+            sess.removed = args.value_of("removed").map_or(Removal::Ask, |x| x.parse().unwrap());
+            if let Err(x) = try_proc(&mut sess, &dir.to_owned()) {
+                error!("{}", x);
+            }
+        },
+        ("diff", Some(args)) => {
+            let profile = local_profile(args.value_of("profile")).unwrap_or_else(|x| panic!("{}", x));
+
+            let dir = fs::canonicalize(args.value_of("DIRECTORY").unwrap_or("./")).expect("Cannot parse path!");
+
+            let mut sess = Session::new(profile, args.value_of("api").map(|x| x.to_owned()));
+            sess.read_only = true;
+            if let Err(x) = try_diff(&mut sess, &dir) {
+                error!("{}", x);
+            }
+        },
+        ("check", Some(args)) => {
+            let profile = if args.is_present("remote") { get_profile(args.value_of("profile")) } else { local_profile(args.value_of("profile")) };
+            let profile = profile.unwrap_or_else(|x| panic!("{}", x));
 
             let dir = fs::canonicalize(args.value_of("DIRECTORY").unwrap_or("./")).expect("Cannot parse path!");
 
-            let mut client = MwClient::new().unwrap();
-            client.login(cfg.username.to_owned(), cfg.password.to_owned()).unwrap();
-            if let Err(x) = try_proc(&cfg, &mut client, &dir.to_owned()) {
+            let mut sess = Session::new(profile, args.value_of("api").map(|x| x.to_owned()));
+            if let Err(x) = try_check(&mut sess, &dir, args.is_present("remote")) {
+                error!("{}", x);
+            }
+        },
+        ("queue", Some(args)) => {
+            let mut queue = Queue::open().unwrap_or_else(|x| panic!("{}", x));
+
+            if args.is_present("all") {
+                queue.entries.clear();
+            } else if let Some(x) = args.values_of("discard") {
+                let mut discard: Vec<usize> = x.map(|x| x.parse().expect("Invalid queue number!")).collect();
+                discard.sort();
+                discard.dedup();
+
+                for i in discard.into_iter().rev() {
+                    if i < queue.entries.len() {
+                        info!("Discarding {}", queue.entries.remove(i).title);
+                    }
+                }
+            }
+
+            queue.save().expect("Error writing queue!");
+
+            if args.is_present("flush") {
+                let profile = get_profile(args.value_of("profile")).unwrap_or_else(|x| panic!("{}", x));
+                let mut sess = Session::new(profile, None);
+
+                match try_flush(&mut sess, &mut queue) {
+                    Ok(x) => info!("Sent {} queued change(s)", x),
+                    Err(x) => error!("{}", x)
+                }
+            }
+
+            queue.list();
+        },
+        ("pull", Some(args)) => {
+            let profile = get_profile(args.value_of("profile")).unwrap_or_else(|x| panic!("{}", x));
+
+            let dir = PathBuf::from(args.value_of("DIRECTORY").unwrap_or("./"));
+            fs::create_dir_all(&dir).expect("Error creating directory!");
+            let dir = fs::canonicalize(dir).expect("Cannot parse path!");
+
+            let api = args.value_of("api").map(|x| x.to_owned());
+            let mut sess = Session::new(profile, api.clone());
+
+            if let Err(x) = sess.client(None).and_then(|client| pull(client, args.value_of("TITLE").unwrap(), &dir, api, args.is_present("force"))) {
                 error!("{}", x);
             }
         },
         ("watch", Some(args)) => {
-            let cfg = get_cfg();
+            let profile = if args.is_present("dry-run") { local_profile(args.value_of("profile")) } else { get_profile(args.value_of("profile")) };
+            let profile = profile.unwrap_or_else(|x| panic!("{}", x));
 
             let dir = fs::canonicalize(args.value_of("DIRECTORY").unwrap_or("./")).expect("Cannot parse path!");
 
-            let mut client = MwClient::new().unwrap();
-            client.login(cfg.username.to_owned(), cfg.password.to_owned()).unwrap();
+            let mut sess = Session::new(profile, args.value_of("api").map(|x| x.to_owned()));
+            sess.force = args.is_present("force");
+            sess.dry_run = args.is_present("dry-run");
+            //asking would hold up the watch loop on stdin
+            sess.removed = args.value_of("removed").map_or(Removal::Keep, |x| x.parse().unwrap());
 
             let (tx, rx) = channel();
             let mut watcher: RecommendedWatcher = Watcher::new(tx, Duration::from_secs(WATCH_WAIT)).unwrap();
 
             watcher.watch(&dir, RecursiveMode::Recursive).unwrap();
 
+            let mut queue = Queue::open().unwrap_or_else(|x| panic!("{}", x));
+            let mut wait = QUEUE_WAIT;
+
+            info!("Watching {} for changes...", dir.display());
+
             loop {
-                match rx.recv() {
-                    Ok(x) => {
-                        match x {
-                            DebouncedEvent::Create(path) | DebouncedEvent::Remove(path) | DebouncedEvent::Rename(_, path) => {
-                                if let Err(x) = try_watch(&cfg, &mut client, &dir, path) {
-                                    error!("Error updating watched folder: {}", x);
-                                }
-                            }, _ => ()
+                if !queue.entries.is_empty() && !sess.dry_run {
+                    match try_flush(&mut sess, &mut queue) {
+                        Ok(x) => {
+                            info!("Sent {} queued change(s)", x);
+                            wait = QUEUE_WAIT;
+                        },
+                        Err(x) => {
+                            warn!("{} change(s) still queued, retrying in {}s: {}", queue.entries.len(), wait, x);
+                            wait = (wait * 2).min(QUEUE_MAX_WAIT);
                         }
-                    },
-                    Err(x) => error!("Watch error: {}", x)
+                    }
+                }
+
+                let first = if queue.entries.is_empty() || sess.dry_run {
+                    rx.recv().map_err(|x| x.to_string())
+                } else {
+                    match rx.recv_timeout(Duration::from_secs(wait)) {
+                        Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
+                        x => x.map_err(|x| x.to_string())
+                    }
+                };
+
+                let first = match first {
+                    Ok(x) => x,
+                    Err(x) => {
+                        error!("Watch error: {}", x);
+                        break;
+                    }
+                };
+
+                //the debouncer hands over events of one window in quick succession
+                let mut events = vec![first];
+                while let Ok(x) = rx.recv_timeout(Duration::from_millis(WATCH_BATCH)) {
+                    events.push(x);
                 }
+
+                let mut paths = Vec::new();
+                for x in events {
+                    match x {
+                        DebouncedEvent::Create(path) | DebouncedEvent::Write(path) | DebouncedEvent::Remove(path) => paths.push(path),
+                        DebouncedEvent::Rename(from, to) => {
+                            paths.push(from);
+                            paths.push(to);
+                        },
+                        DebouncedEvent::Error(x, path) => error!("Watch error{}: {}", path.map(|x| format!(" on {}", x.display())).unwrap_or_default(), x),
+                        _ => ()
+                    }
+                }
+
+                try_watch(&mut sess, &mut queue, &dir, paths);
             }
 
         }
@@ -0,0 +1,124 @@
+use super::*;
+use ring::digest;
+use std::time::SystemTime;
+
+pub const MANIFEST_FILE: &str = "manifest.toml";
+//single timestamp used before the manifest
+pub const MOD_FILE: &str = "mod.toml";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Entry {
+    //sha256 of the published wikitext or image
+    pub hash: String,
+    //page title, or file name for images
+    pub title: String,
+    pub revid: Option<u64>,
+    pub timestamp: Option<String>
+}
+
+impl Entry {
+    pub fn revision(&self) -> Option<Revision> {
+        match (self.revid, &self.timestamp) {
+            (Some(revid), Some(timestamp)) => Some(Revision {revid, timestamp: timestamp.clone()}),
+            _ => None
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LegacyMod {
+    last_mod: SystemTime,
+    #[serde(default)]
+    pages: HashMap<String, Revision>
+}
+
+//path relative to the project -> what was last published from it
+#[derive(Serialize, Deserialize, Default)]
+pub struct Manifest {
+    #[serde(skip)]
+    dir: PathBuf,
+    #[serde(skip)]
+    legacy: Option<LegacyMod>,
+    #[serde(default)]
+    files: HashMap<String, Entry>
+}
+
+pub fn hash(data: &[u8]) -> String {
+    digest::digest(&digest::SHA256, data).as_ref().iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+impl Manifest {
+    pub fn new(dir: &PathBuf) -> Self {
+        Manifest { dir: dir.clone(), ..Manifest::default() }
+    }
+
+    pub fn open(dir: &PathBuf) -> Res<Self> {
+        let mut manifest: Manifest = match fs::read_to_string(dir.with(MANIFEST_FILE)) {
+            Ok(x) => toml::from_str(&x)?,
+            Err(_) => Manifest::default()
+        };
+
+        manifest.dir = dir.clone();
+        if manifest.files.is_empty() {
+            manifest.legacy = fs::read_to_string(dir.with(MOD_FILE)).ok().and_then(|x| toml::from_str(&x).ok());
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn save(&self) -> Res<()> {
+        fs::write(self.dir.with(MANIFEST_FILE), toml::to_string(self)?)?;
+
+        if self.legacy.is_some() && self.dir.with(MOD_FILE).exists() {
+            fs::remove_file(self.dir.with(MOD_FILE))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, rel: &str) -> Option<&Entry> {
+        self.files.get(rel)
+    }
+
+    //whether path has to be published, files the old mod.toml saw being packed get adopted as they are
+    pub fn changed(&mut self, rel: &str, title: &str, path: &Path, hash: &str) -> bool {
+        if let Some(x) = self.files.get(rel) {
+            return x.hash != hash || x.title != title;
+        }
+
+        let adopt = match &self.legacy {
+            Some(legacy) => fs::metadata(path).and_then(|x| x.modified()).map(|x| x <= legacy.last_mod).unwrap_or(false),
+            None => false
+        };
+
+        if adopt {
+            let base = self.base(title);
+            self.record(rel, Entry {
+                hash: hash.to_owned(), title: title.to_owned(),
+                revid: base.as_ref().map(|x| x.revid), timestamp: base.map(|x| x.timestamp)
+            });
+        }
+
+        !adopt
+    }
+
+    //last revision we published of a page
+    pub fn base(&self, title: &str) -> Option<Revision> {
+        self.files.values().filter(|x| x.title == title).find_map(|x| x.revision())
+            .or_else(|| self.legacy.as_ref().and_then(|x| x.pages.get(title).cloned()))
+    }
+
+    pub fn record(&mut self, rel: &str, entry: Entry) {
+        self.files.insert(rel.to_owned(), entry);
+    }
+
+    pub fn forget(&mut self, rel: &str) {
+        self.files.remove(rel);
+    }
+
+    //published files that are gone from the project
+    pub fn stale(&self, seen: &[String]) -> Vec<(String, Entry)> {
+        self.files.iter().filter(|(rel, _)| !seen.contains(rel))
+            .map(|(rel, x)| (rel.clone(), x.clone())).collect()
+    }
+}
@@ -0,0 +1,48 @@
+use super::*;
+
+//heading of the table of contents on the index page
+pub const CONTENTS: &str = "Contents";
+
+//leading digits of the file name, like 01-intro
+fn number(name: &str) -> Option<u64> {
+    let file = name.rsplit('/').next().unwrap_or(name);
+    let digits: String = file.chars().take_while(|x| x.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+//listed in order first, then by numeric prefix, then by name
+pub fn sort_sections<T>(sections: &mut [(String, String, T)], order: &[String]) {
+    for x in order {
+        if !sections.iter().any(|(name, _, _)| name == x) {
+            warn!("{} lists {} in its order, but there is no such section", META_FILE, x);
+        }
+    }
+
+    sections.sort_by(|(a, _, _), (b, _, _)| {
+        //unnumbered after numbered
+        let key = |x: &String| (order.iter().position(|y| y == x).unwrap_or(order.len()), number(x).map_or((1, 0), |n| (0, n)));
+        key(a).cmp(&key(b)).then_with(|| a.cmp(b))
+    });
+}
+
+pub fn contents(title: &str, names: &[String]) -> String {
+    let items: Vec<String> = names.iter().map(|x| format!("# [[{}|{}]]", section(title, x), x)).collect();
+    format!("== {} ==\n{}", CONTENTS, items.join("\n"))
+}
+
+//up is a page title, prev and next are section names
+pub fn nav_box(title: &str, up: Option<&str>, prev: Option<&str>, next: Option<&str>) -> String {
+    let link = |x: &str| format!("[[{}|{}]]", section(title, x), x);
+
+    let prev = prev.map(|x| format!("← {}", link(x))).unwrap_or_default();
+    let next = next.map(|x| format!("{} →", link(x))).unwrap_or_default();
+    let middle = match up {
+        Some(up) => format!("[[{}]] · [[{}#{}|{}]]", up, title, CONTENTS, CONTENTS),
+        None => format!("[[{}#{}|{}]]", title, CONTENTS, CONTENTS)
+    };
+
+    let cells: Vec<String> = [(33, prev), (34, middle), (33, next)].iter()
+        .map(|(width, x)| format!("| style=\"width: {}%;\" | {}", width, x).trim_end().to_owned()).collect();
+
+    format!("{{| class=\"wikitable\" style=\"width: 100%; text-align: center;\"\n{}\n|}}", cells.join("\n"))
+}
@@ -0,0 +1,162 @@
+use super::*;
+
+//the one place pandoc is still needed, there is no native wikitext reader
+fn to_markdown(text: &str) -> Res<String> {
+    let mut p = pandoc::new();
+    p.set_input(pandoc::InputKind::Pipe(text.to_owned())).set_output(pandoc::OutputKind::Pipe)
+        .add_pandoc_path_hint("C:\\Program Files\\Pandoc")
+        .set_input_format(pandoc::InputFormat::MediaWiki, vec![])
+        .set_output_format(pandoc::OutputFormat::Markdown, vec![]);
+
+    match p.execute() {
+        Ok(pandoc::PandocOutput::ToBuffer(s)) => Ok(s),
+        Ok(_) => unreachable!("AAAAAAAAAAAAAAAAAAAAAAAAAAAA PANDOKKK"),
+        Err(pandoc::PandocError::PandocNotFound) =>
+            Err(format_err!("Pulling needs pandoc to turn wikitext into markdown! Install it from https://pandoc.org/installing.html and put it on your PATH.")),
+        Err(x) => Err(x.into())
+    }
+}
+
+//categories become tags in meta.toml, so they are cut out of the text
+fn take_categories(text: &str, tags: &mut Vec<String>) -> Res<(String, bool)> {
+    let cat_regex = Regex::new(r"(?i)\[\[\s*category\s*:\s*([^\]|]+?)\s*(\|[^\]]*)?\]\]\n?")?;
+
+    for x in cat_regex.captures_iter(text) {
+        let tag = x[1].replace('_', " ");
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+
+    Ok((cat_regex.replace_all(text, "").trim().to_owned(), cat_regex.is_match(text)))
+}
+
+fn file_names(text: &str) -> Res<Vec<String>> {
+    let file_regex = Regex::new(r"(?i)\[\[\s*(?:file|image)\s*:\s*([^\]|]+?)\s*(\|[^\]]*)?\]\]")?;
+    let mut names: Vec<String> = file_regex.captures_iter(text).map(|x| x[1].to_owned()).collect();
+
+    //one file per line, the File: is optional
+    let gallery_regex = Regex::new(r"(?is)<gallery[^>]*>(.*?)</gallery>")?;
+    let prefix_regex = Regex::new(r"(?i)^(?:file|image)\s*:")?;
+    for x in gallery_regex.captures_iter(text) {
+        names.extend(x[1].lines().map(|line| prefix_regex.replace(line.split('|').next().unwrap_or("").trim(), "").trim().to_owned())
+            .filter(|x| !x.is_empty()));
+    }
+
+    Ok(names)
+}
+
+fn is_redirect(text: &str) -> bool {
+    text.trim_start().to_lowercase().starts_with("#redirect")
+}
+
+pub fn pull(client: &mut MwClient, title: &str, dir: &PathBuf, api: Option<String>, force: bool) -> Res<()> {
+    if dir.with(META_FILE).exists() && !force {
+        return Err(format_err!("{} already has a {}! Use --force to overwrite it.", dir.display(), META_FILE));
+    }
+
+    let title = &title.replace('_', " ");
+    let prefix = section(title, "");
+
+    info!("Listing subpages of {}...", title);
+    let mut titles = vec![title.to_owned()];
+    titles.append(&mut client.prefixed_pages(&prefix)?);
+
+    let pages = client.page_contents(&titles)?;
+    if !pages.iter().any(|(x, _)| x == title) {
+        return Err(format_err!("{} doesn't exist on the wiki!", title));
+    }
+
+    let mut tags = Vec::new();
+    //categories on subpages are put back on every section
+    let mut section_categories = false;
+    let mut images = Vec::new();
+    let mut manifest = Manifest::new(dir);
+    //hashed once meta.toml is there, packing needs the project title
+    let mut written = Vec::new();
+
+    for (page, rev) in pages {
+        let text = rev.text().unwrap_or("");
+        if is_redirect(text) {
+            debug!("Skipping redirect {}", page);
+            continue;
+        }
+
+        let path = if page.starts_with(&prefix) {
+            dir.with(format!("{}.md", &page[prefix.len()..]))
+        } else {
+            dir.with(INDEX_FILE)
+        };
+
+        info!("Converting {} to {}...", page, path.display());
+        let (text, categorized) = take_categories(text, &mut tags)?;
+        section_categories |= categorized && page != *title;
+        //parse_md looks for images next to the page using them
+        let page_dir = path.parent().unwrap().to_path_buf();
+        images.extend(file_names(&text)?.into_iter().map(|x| (page_dir.clone(), x)));
+
+        fs::create_dir_all(&page_dir)?;
+        fs::write(&path, to_markdown(&text)?)?;
+        written.push((path, page, rev.revision(), text));
+    }
+
+    let mut names: Vec<String> = images.iter().map(|(_, x)| x.clone()).collect();
+    names.sort();
+    names.dedup();
+
+    let urls = client.image_urls(&names)?;
+    for (page_dir, image) in images {
+        match urls.get(&image.replace('_', " ")) {
+            Some(url) => {
+                info!("Downloading image {}...", image);
+                let path = page_dir.with(&image);
+                let data = client.download(url)?;
+                fs::write(&path, &data)?;
+
+                let rel = path.strip_prefix(dir)?.to_string_lossy().to_string();
+                manifest.record(&rel, Entry {hash: hash(&data), title: image, revid: None, timestamp: None});
+            },
+            None => warn!("Image {} doesn't exist on the wiki", image)
+        }
+    }
+
+    let meta = Metadata {
+        title: title.to_owned(),
+        summary: String::new(),
+        source: "wiki".to_owned(),
+        type_: "article".to_owned(),
+        tags,
+        stats: Vec::new(),
+        sub: None,
+        api,
+        converter: None,
+        category: None,
+        section_categories,
+        nav: false,
+        order: Vec::new(),
+        categories: HashMap::new(),
+        infobox: None
+    };
+
+    fs::write(dir.with(META_FILE), toml::to_string(&meta)?)?;
+
+    let conv = converter(None)?;
+    for (path, page, rev, text) in written {
+        //hash what packing the file gives back, so the next pack only publishes what gets edited
+        let rel = path.strip_prefix(dir)?.to_string_lossy().to_string();
+        let (packed, packed_images) = parse_md(&path, conv.as_ref())?;
+        let packed = if page == *title || section_categories { with_categories(&packed, &meta.tags) } else { packed };
+
+        //images get their project prefixed names on the next pack, which has to publish the page with them
+        let names = file_names(&text)?;
+        let renamed = packed_images.iter().any(|x| !names.iter().any(|y| same_title(y, &x.name)));
+        let hash = if renamed { hash(text.as_bytes()) } else { hash(packed.as_bytes()) };
+
+        manifest.record(&rel, Entry {hash, title: page, revid: Some(rev.revid), timestamp: Some(rev.timestamp)});
+    }
+
+    manifest.save()?;
+
+    info!("Pulled {}! Fill in the summary, source and type in {}.", title, META_FILE);
+    Ok(())
+}
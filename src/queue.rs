@@ -0,0 +1,91 @@
+use super::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const QUEUE_FILE: &str = "queue.json";
+//seconds between drain attempts while the wiki is away, doubled up to QUEUE_MAX_WAIT
+pub const QUEUE_WAIT: u64 = 5;
+pub const QUEUE_MAX_WAIT: u64 = 300;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Job {
+    Edit { text: String, summary: String },
+    Upload { path: PathBuf }
+}
+
+//an edit or upload that couldn't reach the wiki
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Queued {
+    pub project: PathBuf,
+    pub api: String,
+    pub rel: String,
+    pub title: String,
+    pub hash: String,
+    pub job: Job,
+    //unix seconds
+    pub queued: u64,
+    pub error: String
+}
+
+impl Queued {
+    pub fn new(project: &PathBuf, api: &str, rel: String, title: String, hash: String, job: Job, error: String) -> Self {
+        let queued = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+        Queued { project: project.clone(), api: api.to_owned(), rel, title, hash, job, queued, error }
+    }
+}
+
+#[derive(Fail, Debug)]
+#[fail(display = "Wiki is unreachable, {} change(s) were not sent: {}", count, cause)]
+pub struct Unsent {
+    pub count: usize,
+    pub cause: String,
+    pub jobs: Vec<Queued>
+}
+
+pub struct Queue {
+    path: PathBuf,
+    pub entries: Vec<Queued>
+}
+
+impl Queue {
+    pub fn open() -> Res<Self> {
+        let path = dirs::data_local_dir().ok_or(format_err!("Could not find data directory!"))?.with(SESSION_DIR).with(QUEUE_FILE);
+        let entries = match fs::read_to_string(&path) {
+            Ok(x) => serde_json::from_str(&x)?,
+            Err(_) => Vec::new()
+        };
+
+        Ok(Queue { path, entries })
+    }
+
+    pub fn save(&self) -> Res<()> {
+        fs::create_dir_all(self.path.parent().unwrap())?;
+        fs::write(&self.path, serde_json::to_string_pretty(&self.entries)?)?;
+        Ok(())
+    }
+
+    //newer content of the same file replaces what was queued
+    pub fn push(&mut self, x: Queued) {
+        self.entries.retain(|y| y.project != x.project || y.rel != x.rel);
+        self.entries.push(x);
+    }
+
+    //a successful pack published everything the project had queued
+    pub fn discard_project(&mut self, project: &PathBuf) -> usize {
+        let len = self.entries.len();
+        self.entries.retain(|x| &x.project != project);
+        len - self.entries.len()
+    }
+
+    pub fn list(&self) {
+        if self.entries.is_empty() {
+            println!("Queue is empty");
+        }
+
+        for (i, x) in self.entries.iter().enumerate() {
+            let kind = match x.job { Job::Edit {..} => "edit", Job::Upload {..} => "upload" };
+            println!("{:>3} {:<6} {} ({}) @ {}, queued {}s ago: {}", i, kind, x.title, x.project.display(), x.api,
+                SystemTime::now().duration_since(UNIX_EPOCH).map(|y| y.as_secs().saturating_sub(x.queued)).unwrap_or(0), x.error);
+        }
+    }
+}
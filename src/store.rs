@@ -0,0 +1,107 @@
+use super::*;
+use ring::{aead, pbkdf2, digest};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::num::NonZeroU32;
+
+pub const STORE_FILE: &str = "bibanon_packer.keys";
+pub const PASSPHRASE_VAR: &str = "BIBANON_PACKER_PASSPHRASE";
+
+const PBKDF2_ROUNDS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+#[derive(Serialize, Deserialize)]
+struct StoreFile {
+    salt: String,
+    nonce: String,
+    data: String
+}
+
+//profile name -> password or token, encrypted with a passphrase
+pub struct Store {
+    path: PathBuf,
+    salt: Vec<u8>,
+    key: [u8; KEY_LEN],
+    secrets: HashMap<String, String>
+}
+
+fn store_path() -> PathBuf {
+    dirs::config_dir().expect("Could not find config directory! Try a more standardized distribution.").with(STORE_FILE)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; KEY_LEN] {
+    let mut key = [0; KEY_LEN];
+    pbkdf2::derive(&digest::SHA256, NonZeroU32::new(PBKDF2_ROUNDS).unwrap(), salt, passphrase.as_bytes(), &mut key);
+    key
+}
+
+fn read_passphrase(confirm: bool) -> Res<String> {
+    if let Ok(x) = std::env::var(PASSPHRASE_VAR) {
+        return Ok(x);
+    }
+
+    println!("Please enter the passphrase of the credential store:");
+    let passphrase = rpassword::read_password()?;
+
+    if confirm {
+        println!("Please repeat the passphrase:");
+        if rpassword::read_password()? != passphrase {
+            return Err(format_err!("Passphrases don't match!"));
+        }
+    }
+
+    Ok(passphrase)
+}
+
+impl Store {
+    pub fn open() -> Res<Self> {
+        let path = store_path();
+
+        if !path.exists() {
+            info!("Creating credential store at {}", path.display());
+            let mut salt = vec![0; SALT_LEN];
+            SystemRandom::new().fill(&mut salt)?;
+
+            let key = derive_key(&read_passphrase(true)?, &salt);
+            return Ok(Store { path, salt, key, secrets: HashMap::new() });
+        }
+
+        let file: StoreFile = toml::from_str(&fs::read_to_string(&path)?)?;
+        let salt = base64::decode(&file.salt)?;
+        let nonce = aead::Nonce::try_assume_unique_for_key(&base64::decode(&file.nonce)?)?;
+        let mut data = base64::decode(&file.data)?;
+
+        let key = derive_key(&read_passphrase(false)?, &salt);
+        let opening = aead::OpeningKey::new(&aead::CHACHA20_POLY1305, &key)?;
+        let plain = aead::open_in_place(&opening, nonce, aead::Aad::empty(), 0, &mut data)
+            .map_err(|_| format_err!("Could not decrypt {}; wrong passphrase?", path.display()))?;
+
+        let secrets = toml::from_str(std::str::from_utf8(plain)?)?;
+        Ok(Store { path, salt, key, secrets })
+    }
+
+    pub fn get(&self, profile: &str) -> Res<String> {
+        self.secrets.get(profile).cloned()
+            .ok_or(format_err!("No password stored for profile {}! Set it with `credentials {} --password`.", profile, profile))
+    }
+
+    pub fn set(&mut self, profile: &str, secret: String) {
+        self.secrets.insert(profile.to_owned(), secret);
+    }
+
+    pub fn save(&self) -> Res<()> {
+        let mut nonce = [0; aead::NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce)?;
+
+        let sealing = aead::SealingKey::new(&aead::CHACHA20_POLY1305, &self.key)?;
+        let mut data = toml::to_string(&self.secrets)?.into_bytes();
+        data.extend_from_slice(&[0; aead::MAX_TAG_LEN]);
+
+        let len = aead::seal_in_place(&sealing, aead::Nonce::assume_unique_for_key(nonce), aead::Aad::empty(), &mut data, aead::MAX_TAG_LEN)?;
+        data.truncate(len);
+
+        let file = StoreFile { salt: base64::encode(&self.salt), nonce: base64::encode(&nonce), data: base64::encode(&data) };
+        fs::write(&self.path, toml::to_string(&file)?)?;
+        Ok(())
+    }
+}